@@ -13,6 +13,7 @@ import (
 	"client/matrix/mtype"
 	"client/message"
 	messageType "client/message/mtype"
+	"client/token"
 )
 
 func runDaemonMainLoop(server string) error {
@@ -34,13 +35,13 @@ func runDaemonMainLoop(server string) error {
 	// delete the file when the daemon exits
 	defer os.Remove(daemonFileName)
 
-	// establish connection to the server
-	serverConnection, err := net.Dial("tcp", server)
+	// establish connection to the server, which starts with a hello
+	serverConnection, err := message.Connect(server)
 	if err != nil {
 		return fmt.Errorf("daemon %d error: error connecting to the server: %s", id, err)
 	}
 	defer serverConnection.Close()
-	clientId := uint16(serverConnection.LocalAddr().(*net.TCPAddr).Port)
+	clientId := serverConnection.LocalPort()
 
 	// notify the client about ports
 	fmt.Printf("{\"kind\":\"listen\",\"port\":\"%d\"}\n", id)
@@ -71,9 +72,10 @@ func runDaemonMainLoop(server string) error {
 		switch cmd {
 		case command.Reserve:
 			request = message.NewRequest(clientId, messageType.Reserve, map[string]string{
-				"matrixType":      args[1],
-				"matrixDimension": args[2],
-				"file":            args[3],
+				"matrixType":    args[1],
+				"matrixRows":    args[2],
+				"matrixColumns": args[3],
+				"file":          args[4],
 			})
 			break
 		case command.Calc:
@@ -115,11 +117,12 @@ func main() {
 
 	// sendData command arguments
 	mTypeStr := flag.String("type", "", "the matrix type")
-	mDimension := flag.String("dimension", "", "the dimension of the matrix")
+	mRows := flag.String("rows", "", "the number of rows of the matrix")
+	mColumns := flag.String("columns", "", "the number of columns of the matrix")
 	mFileName := flag.String("file", "", "the file to read the matrix from")
 
 	// startCalculation, getStatus command arguments
-	jobId := flag.Uint("job-id", 0, "the id of a registered job")
+	jobId := flag.String("job-id", "", "the id of a registered job")
 
 	flag.Parse()
 
@@ -188,8 +191,9 @@ func main() {
 
 		break
 	case command.Calc, command.Poll:
-		if *jobId == 0 {
-			fmt.Fprintf(os.Stderr, "Invalid id argument: must be a strictly positive integer.\n")
+		_, err := token.FromString(*jobId)
+		if err != nil {
+			fmt.Fprintln(os.Stderr, err)
 			os.Exit(1)
 		}
 		break
@@ -206,7 +210,7 @@ func main() {
 	// send the command
 	switch cmd {
 	case command.Reserve:
-		_, err = fmt.Fprintln(connection, *commandStr, *mTypeStr, *mDimension, *mFileName)
+		_, err = fmt.Fprintln(connection, *commandStr, *mTypeStr, *mRows, *mColumns, *mFileName)
 		break
 	case command.Calc:
 		_, err = fmt.Fprintln(connection, *commandStr, *jobId)
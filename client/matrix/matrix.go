@@ -4,28 +4,29 @@ import (
 	"client/constants"
 	"client/matrix/mtype"
 	"fmt"
+	"io"
 	"net"
 	"os"
 	"strings"
 )
 
-const CHUNK_SIZE = 1500
-
 type Matrix struct {
-	Type      mtype.MatrixType
-	Dimension uint32
-	FilePath  string
+	Type     mtype.MatrixType
+	Rows     uint32
+	Columns  uint32
+	FilePath string
 }
 
 func (matrix Matrix) GetByteLen() uint64 {
-	return uint64(matrix.Type.GetByteSize()) * uint64(matrix.Dimension) * uint64(matrix.Dimension)
+	return uint64(matrix.Type.GetByteSize()) * uint64(matrix.Rows) * uint64(matrix.Columns)
 }
 
 func (matrix Matrix) String() string {
-	return fmt.Sprintf("%dx%d matrix of type %s", matrix.Dimension, matrix.Dimension, matrix.Type)
+	return fmt.Sprintf("%dx%d matrix of type %s", matrix.Rows, matrix.Columns, matrix.Type)
 }
 
-func (matrix Matrix) FromTCPStreamToFile(con net.Conn) error {
+// creates the file in the downloads folder the result of the matrix is written to
+func (matrix Matrix) CreateDownloadedFile(con net.Conn) (*os.File, error) {
 	clientId := uint16(con.LocalAddr().(*net.TCPAddr).Port)
 	downloadsFolder := fmt.Sprintf("%s/%d", constants.DOWNLOADS_FOLDER, clientId)
 	os.Mkdir(downloadsFolder, os.ModeDir|os.ModePerm)
@@ -36,103 +37,43 @@ func (matrix Matrix) FromTCPStreamToFile(con net.Conn) error {
 
 	downloadedFile, err := os.Create(downloadedFileName)
 	if err != nil {
-		return fmt.Errorf("Error creating file %s: %s", downloadedFileName, err)
+		return nil, fmt.Errorf("Error creating file %s: %s", downloadedFileName, err)
 	}
-	defer downloadedFile.Close()
-
-	chunks := make(chan []uint8)
-	defer close(chunks)
-
-	matrixByteLen := matrix.GetByteLen()
-	chunkCount := uint32((matrixByteLen-1)/CHUNK_SIZE + 1)
-	go func() {
-		leftToRead := matrixByteLen
-		for i := uint32(0); i < chunkCount; i++ {
-			var chunkLen int
-			if leftToRead < CHUNK_SIZE {
-				chunkLen = int(leftToRead)
-			} else {
-				chunkLen = CHUNK_SIZE
-			}
-			leftToRead -= uint64(chunkLen)
-
-			chunk := make([]uint8, chunkLen)
-
-			totalN := 0
-			for totalN < chunkLen {
-				n, err := con.Read(chunk[totalN:])
-
-				if err != nil {
-					fmt.Fprintf(os.Stderr, "Error reading chunk %d from TCP stream: %s\n", i, err)
-				}
-
-				if uint32(n) == 0 {
-					fmt.Fprintln(os.Stderr, "The server disconnected")
-					os.Exit(1)
-				}
-
-				totalN += n
-			}
+	return downloadedFile, nil
+}
 
-			chunks <- chunk
-		}
-	}()
+// reads byteLen bytes of a matrix from the TCP stream into the downloads folder
+func (matrix Matrix) FromTCPStreamToFile(con net.Conn, byteLen uint64) error {
+	downloadedFile, err := matrix.CreateDownloadedFile(con)
+	if err != nil {
+		return err
+	}
+	defer downloadedFile.Close()
 
-	for i := uint32(0); i < chunkCount; i++ {
-		_, err = downloadedFile.Write(<-chunks)
-		if err != nil {
-			return fmt.Errorf("Error writing to file %s: %s", downloadedFileName, err)
-		}
+	_, err = io.CopyN(downloadedFile, con, int64(byteLen))
+	if err != nil {
+		return fmt.Errorf("Error downloading to file %s: %s", downloadedFile.Name(), err)
 	}
 
 	return nil
 }
 
-func (matrix Matrix) FromFileToTCPStream(con net.Conn) error {
+// writes byteLen bytes of the matrix file, starting at offset, to the TCP stream
+func (matrix Matrix) FromFileToTCPStream(con net.Conn, offset uint64, byteLen uint64) error {
 	file, err := os.Open(matrix.FilePath)
 	if err != nil {
 		return fmt.Errorf("Error opening file %s: %s", matrix.FilePath, err)
 	}
 	defer file.Close()
 
-	chunks := make(chan []uint8)
-	defer close(chunks)
-
-	matrixByteLen := matrix.GetByteLen()
-	chunkCount := uint32((matrixByteLen-1)/CHUNK_SIZE + 1)
-	go func() {
-		leftToWrite := matrixByteLen
-		for i := uint32(0); i < chunkCount; i++ {
-			var chunkLen uint32
-			if leftToWrite < CHUNK_SIZE {
-				chunkLen = uint32(leftToWrite)
-			} else {
-				chunkLen = CHUNK_SIZE
-			}
-			leftToWrite -= uint64(chunkLen)
-
-			chunk := make([]uint8, chunkLen)
-
-			_, err := file.Read(chunk)
-			if err != nil {
-				fmt.Fprintf(os.Stderr, "Error reading chunk %d from matrix file: %s\n", i, err)
-				os.Exit(1)
-			}
-
-			chunks <- chunk
-		}
-	}()
-
-	for i := uint32(0); i < chunkCount; i++ {
-		n, err := con.Write(<-chunks)
-
-		if err != nil {
-			return fmt.Errorf("Error writing chunk %d to TCPStream: %s", i, err)
-		}
+	_, err = file.Seek(int64(offset), io.SeekStart)
+	if err != nil {
+		return fmt.Errorf("Error seeking to byte %d of file %s: %s", offset, matrix.FilePath, err)
+	}
 
-		if n == 0 {
-			return fmt.Errorf("The server disconnected")
-		}
+	_, err = io.CopyN(con, file, int64(byteLen))
+	if err != nil {
+		return fmt.Errorf("Error writing file %s to TCP stream: %s", matrix.FilePath, err)
 	}
 
 	return nil
@@ -0,0 +1,157 @@
+package message
+
+import (
+	messageType "client/message/mtype"
+	"encoding/binary"
+	"fmt"
+	"io"
+	"net"
+)
+
+// the protocol version this client speaks
+const PROTOCOL_VERSION = 2
+
+// a frame header: a 64-bit payload length, the message code and a 32-bit
+// correlation ID, all little-endian
+const HEADER_LEN = 13
+
+// the part of the hello response the client reads, up to the capability mask
+const HELLO_RESPONSE_LEN = 28
+
+// A connection to the server that has already been through the hello. The
+// requests are sent one at a time, every one with a new correlation ID.
+type Connection struct {
+	con               net.Conn
+	nextCorrelationId uint32
+	// the longest payload the server accepts in one request
+	MaxFrameLen uint64
+}
+
+func Connect(server string) (connection *Connection, err error) {
+	con, err := net.Dial("tcp", server)
+	if err != nil {
+		return
+	}
+
+	connection = &Connection{con: con}
+	err = connection.hello()
+	if err != nil {
+		con.Close()
+		connection = nil
+	}
+	return
+}
+
+func (connection *Connection) LocalPort() uint16 {
+	return uint16(connection.con.LocalAddr().(*net.TCPAddr).Port)
+}
+
+func (connection *Connection) Close() error {
+	return connection.con.Close()
+}
+
+func (connection *Connection) hello() error {
+	payload := [2]uint8{}
+	binary.LittleEndian.PutUint16(payload[:], PROTOCOL_VERSION)
+	correlationId, err := connection.send(messageType.Hello, payload[:])
+	if err != nil {
+		return fmt.Errorf("error sending hello: %s", err)
+	}
+
+	responseType, response, err := connection.receiveAll(correlationId)
+	if err != nil {
+		return fmt.Errorf("error receiving hello response: %s", err)
+	}
+
+	switch responseType {
+	case messageType.Hello:
+		if len(response) < HELLO_RESPONSE_LEN {
+			return fmt.Errorf("the hello response is too short: %d bytes", len(response))
+		}
+		connection.MaxFrameLen = binary.LittleEndian.Uint64(response[8:16])
+		return nil
+	case messageType.Error:
+		code, message, err := decodeError(response)
+		if err != nil {
+			return err
+		}
+		return fmt.Errorf("the server refused the hello: error %d: %s", code, message)
+	}
+
+	return fmt.Errorf("unexpected response to hello: %s", responseType)
+}
+
+// writes a frame header, the payload has to be written right after it
+func (connection *Connection) startFrame(mType messageType.MessageType, payloadLen uint64) (correlationId uint32, err error) {
+	correlationId = connection.nextCorrelationId
+	connection.nextCorrelationId++
+
+	header := [HEADER_LEN]uint8{}
+	binary.LittleEndian.PutUint64(header[0:8], payloadLen)
+	header[8] = mType.Encode()
+	binary.LittleEndian.PutUint32(header[9:13], correlationId)
+	_, err = connection.con.Write(header[:])
+	return
+}
+
+func (connection *Connection) send(mType messageType.MessageType, payload []uint8) (correlationId uint32, err error) {
+	correlationId, err = connection.startFrame(mType, uint64(len(payload)))
+	if err != nil {
+		return
+	}
+
+	_, err = connection.con.Write(payload)
+	return
+}
+
+// reads a frame header, the payload has to be read right after it
+func (connection *Connection) receive(correlationId uint32) (mType messageType.MessageType, payloadLen uint64, err error) {
+	header := [HEADER_LEN]uint8{}
+	_, err = io.ReadFull(connection.con, header[:])
+	if err != nil {
+		return
+	}
+
+	payloadLen = binary.LittleEndian.Uint64(header[0:8])
+	mType, err = messageType.Decode(header[8])
+	if err != nil {
+		return
+	}
+
+	// only one request is in flight at a time
+	receivedId := binary.LittleEndian.Uint32(header[9:13])
+	if receivedId != correlationId {
+		err = fmt.Errorf("expected a response to request %d, got one to %d", correlationId, receivedId)
+	}
+	return
+}
+
+func (connection *Connection) receiveAll(correlationId uint32) (mType messageType.MessageType, payload []uint8, err error) {
+	mType, payloadLen, err := connection.receive(correlationId)
+	if err != nil {
+		return
+	}
+
+	payload = make([]uint8, payloadLen)
+	_, err = io.ReadFull(connection.con, payload)
+	return
+}
+
+// an error payload is a 16-bit code followed by a 32-bit length of the message
+// and the message itself
+func decodeError(payload []uint8) (code uint16, message string, err error) {
+	if len(payload) < 6 {
+		err = fmt.Errorf("the error response is too short: %d bytes", len(payload))
+		return
+	}
+
+	code = binary.LittleEndian.Uint16(payload[0:2])
+	messageLen := uint64(binary.LittleEndian.Uint32(payload[2:6]))
+	if uint64(len(payload)-6) < messageLen {
+		err = fmt.Errorf("the error message is cut short")
+		return
+	}
+
+	message = string(payload[6 : 6+messageLen])
+	return
+}
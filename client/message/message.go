@@ -6,19 +6,21 @@ import (
 	"client/message/kind"
 	messageType "client/message/mtype"
 	"client/status"
+	"client/token"
+	"encoding/binary"
 	"fmt"
-	"net"
+	"io"
 	"strconv"
 	"time"
-	"unsafe"
 )
 
 type Message struct {
-	Client  uint16
-	Time    time.Time
-	Kind    kind.Kind
-	Type    messageType.MessageType
-	Payload map[string]string
+	Client      uint16
+	Correlation uint32
+	Time        time.Time
+	Kind        kind.Kind
+	Type        messageType.MessageType
+	Payload     map[string]string
 }
 
 type Request Message
@@ -33,20 +35,16 @@ func NewRequest(client uint16, mType messageType.MessageType, payload map[string
 	}
 }
 
-var matrices map[uint64]matrix.Matrix = make(map[uint64]matrix.Matrix)
+var matrices map[token.Token]matrix.Matrix = make(map[token.Token]matrix.Matrix)
 
-func (request *Request) Execute(con net.Conn) (resp Response, err error) {
-	buffer := [1]uint8{request.Type.Encode()}
-	_, err = con.Write(buffer[:])
-	if err != nil {
-		err = fmt.Errorf("error writing request type code to TCP stream: %s", err)
-		return
-	}
+// an upload request carries the job id and a 64-bit offset before the chunk
+const UPLOAD_HEADER_LEN = token.LEN + 8
 
-	var parsed uint64
-	var id uint64
+func (request *Request) Execute(connection *Connection) (resp Response, err error) {
+	var correlationId uint32
+	var id token.Token
 	var mType matrixType.MatrixType
-	var mDimension uint32
+	var mRows, mColumns uint32
 	switch request.Type {
 	case messageType.Reserve:
 		mType, err = matrixType.FromString(request.Payload["matrixType"])
@@ -55,60 +53,74 @@ func (request *Request) Execute(con net.Conn) (resp Response, err error) {
 			return
 		}
 
-		parsed, err = strconv.ParseUint(request.Payload["matrixDimension"], 10, 32)
+		var parsed uint64
+		parsed, err = strconv.ParseUint(request.Payload["matrixRows"], 10, 32)
+		if err != nil {
+			err = fmt.Errorf("error processing a %s request: error parsing matrixRows: %s", request.Type, err)
+			return
+		}
+		mRows = uint32(parsed)
+
+		parsed, err = strconv.ParseUint(request.Payload["matrixColumns"], 10, 32)
 		if err != nil {
-			err = fmt.Errorf("error processing a %s request: error parsing matrixDimension: %s", request.Type, err)
+			err = fmt.Errorf("error processing a %s request: error parsing matrixColumns: %s", request.Type, err)
 			return
 		}
-		mDimension = uint32(parsed)
+		mColumns = uint32(parsed)
 
-		buffer := [5]uint8{}
+		buffer := [9]uint8{}
 		buffer[0] = mType.Encode()
-		*(*uint32)(unsafe.Pointer(&buffer[1])) = mDimension
-		_, err = con.Write(buffer[:])
+		binary.LittleEndian.PutUint32(buffer[1:5], mRows)
+		binary.LittleEndian.PutUint32(buffer[5:9], mColumns)
+		correlationId, err = connection.send(request.Type, buffer[:])
 		if err != nil {
 			err = fmt.Errorf("error processing a %s request: error writing to TCP stream: %s", request.Type, err)
 			return
 		}
 		break
 	case messageType.Calc:
-		id, err = strconv.ParseUint(request.Payload["id"], 10, 64)
+		id, err = token.FromString(request.Payload["id"])
 		if err != nil {
-			err = fmt.Errorf("error processing a %s request: error parsing index: %s", request.Type, err)
+			err = fmt.Errorf("error processing a %s request: %s", request.Type, err)
 			return
 		}
 
 		matrix, ok := matrices[id]
 		if !ok {
 			err = fmt.Errorf("error processing a %s request: You have to reserve "+
-				"a matrix on id %d before requesting calculation on it", request.Type, id)
+				"a matrix on id %s before requesting calculation on it", request.Type, id)
 			return
 		}
 
-		buffer := [8]uint8{}
-		*(*uint64)(unsafe.Pointer(&buffer[0])) = id
-		_, err = con.Write(buffer[:])
+		// a matrix that doesn't fit into one frame is uploaded in chunks
+		if uint64(token.LEN)+matrix.GetByteLen() > connection.MaxFrameLen {
+			request.Time = time.Now()
+			return request.upload(connection, id, matrix)
+		}
+
+		correlationId, err = connection.startFrame(request.Type, uint64(token.LEN)+matrix.GetByteLen())
+		if err == nil {
+			_, err = connection.con.Write(id[:])
+		}
 		if err != nil {
 			err = fmt.Errorf("error processing a %s request: error writing to TCP stream: %s", request.Type, err)
 			return
 		}
 
-		err = matrix.FromFileToTCPStream(con)
+		err = matrix.FromFileToTCPStream(connection.con, 0, matrix.GetByteLen())
 		if err != nil {
 			err = fmt.Errorf("error processing a %s request: error writing to TCP stream: %s", request.Type, err)
 			return
 		}
 		break
 	case messageType.Poll:
-		id, err = strconv.ParseUint(request.Payload["id"], 10, 64)
+		id, err = token.FromString(request.Payload["id"])
 		if err != nil {
-			err = fmt.Errorf("error processing a %s request: error parsing index: %s", request.Type, err)
+			err = fmt.Errorf("error processing a %s request: %s", request.Type, err)
 			return
 		}
 
-		buffer := [8]uint8{}
-		*(*uint64)(unsafe.Pointer(&buffer[0])) = id
-		_, err = con.Write(buffer[:])
+		correlationId, err = connection.send(request.Type, id[:])
 		if err != nil {
 			err = fmt.Errorf("error processing a %s request: error writing to TCP stream: %s", request.Type, err)
 			return
@@ -118,93 +130,267 @@ func (request *Request) Execute(con net.Conn) (resp Response, err error) {
 		err = fmt.Errorf("Invalid request type: %s", request.Type)
 		return
 	}
+	request.Correlation = correlationId
 	request.Time = time.Now()
 
-	_, err = con.Read(buffer[:])
+	responseType, payloadLen, err := connection.receive(correlationId)
 	if err != nil {
-		err = fmt.Errorf("error reading response type code from TCP stream: %s", err)
+		err = fmt.Errorf("error reading response header from TCP stream: %s", err)
 		return
 	}
-	responseCode := buffer[0]
 
-	responseType, err := messageType.Decode(responseCode)
-	if err != nil {
-		return
-	}
 	responsePayload := map[string]string{}
-	switch responseType {
-	case messageType.Reserve:
-		buffer := [8]uint8{}
-		_, err = con.Read(buffer[:])
+	// a completed poll is streamed to a file, every other payload is read whole
+	if responseType == messageType.Poll {
+		err = readPollPayload(connection, id, payloadLen, responsePayload)
+		if err != nil {
+			err = fmt.Errorf("error processing a %s response: %s", responseType, err)
+			return
+		}
+	} else {
+		payload := make([]uint8, payloadLen)
+		_, err = io.ReadFull(connection.con, payload)
 		if err != nil {
 			err = fmt.Errorf("error processing a %s response: error reading from TCP stream: %s", responseType, err)
 			return
 		}
-		id = *(*uint64)(unsafe.Pointer(&buffer[0]))
-		responsePayload["id"] = strconv.FormatUint(id, 10)
 
-		filePath := request.Payload["file"]
-		delete(request.Payload, "file")
-		matrices[id] = matrix.Matrix{
-			Type:      mType,
-			Dimension: mDimension,
-			FilePath:  filePath,
+		switch responseType {
+		case messageType.Reserve:
+			if len(payload) != token.LEN {
+				err = fmt.Errorf("error processing a %s response: invalid id length %d", responseType, len(payload))
+				return
+			}
+			copy(id[:], payload)
+			responsePayload["id"] = id.String()
+
+			filePath := request.Payload["file"]
+			delete(request.Payload, "file")
+			matrices[id] = matrix.Matrix{
+				Type:     mType,
+				Rows:     mRows,
+				Columns:  mColumns,
+				FilePath: filePath,
+			}
+			break
+		case messageType.Error:
+			var code uint16
+			var message string
+			code, message, err = decodeError(payload)
+			if err != nil {
+				err = fmt.Errorf("error processing an %s response: %s", responseType, err)
+				return
+			}
+
+			responsePayload["code"] = strconv.FormatUint(uint64(code), 10)
+			responsePayload["message"] = message
+			break
 		}
-		break
-	case messageType.Poll:
-		_, err = con.Read(buffer[:])
+	}
+
+	resp = Response{
+		Client:      request.Client,
+		Correlation: correlationId,
+		Time:        time.Now(),
+		Kind:        kind.Response,
+		Type:        responseType,
+		Payload:     responsePayload,
+	}
+	return
+}
+
+// sends the matrix in chunks that fit into a frame, the response to the last
+// chunk or the first error is the response to the whole request
+func (request *Request) upload(connection *Connection, id token.Token, m matrix.Matrix) (resp Response, err error) {
+	if connection.MaxFrameLen <= UPLOAD_HEADER_LEN {
+		err = fmt.Errorf("error uploading the %s: the server's frames are too short", m)
+		return
+	}
+	chunkLen := connection.MaxFrameLen - UPLOAD_HEADER_LEN
+
+	byteLen := m.GetByteLen()
+	for offset := uint64(0); offset < byteLen; offset += chunkLen {
+		length := byteLen - offset
+		if length > chunkLen {
+			length = chunkLen
+		}
+
+		var correlationId uint32
+		correlationId, err = connection.startFrame(messageType.Upload, UPLOAD_HEADER_LEN+length)
 		if err != nil {
-			err = fmt.Errorf("error processing a %s response: error reading status type code from TCP stream: %s", responseType, err)
+			err = fmt.Errorf("error uploading the %s: error writing to TCP stream: %s", m, err)
 			return
 		}
 
-		var st status.Status
-		st, err = status.Decode(buffer[0])
+		buffer := [UPLOAD_HEADER_LEN]uint8{}
+		copy(buffer[:token.LEN], id[:])
+		binary.LittleEndian.PutUint64(buffer[token.LEN:], offset)
+		_, err = connection.con.Write(buffer[:])
 		if err != nil {
+			err = fmt.Errorf("error uploading the %s: error writing to TCP stream: %s", m, err)
 			return
 		}
-		responsePayload["status"] = st.String()
 
-		if st == status.Completed {
-			matrix := matrices[id]
+		err = m.FromFileToTCPStream(connection.con, offset, length)
+		if err != nil {
+			err = fmt.Errorf("error uploading the %s: %s", m, err)
+			return
+		}
 
-			err = matrix.FromTCPStreamToFile(con)
+		var responseType messageType.MessageType
+		var payload []uint8
+		responseType, payload, err = connection.receiveAll(correlationId)
+		if err != nil {
+			err = fmt.Errorf("error uploading the %s: error reading from TCP stream: %s", m, err)
+			return
+		}
+
+		responsePayload := map[string]string{}
+		switch responseType {
+		case messageType.Upload:
+			if len(payload) != 8 {
+				err = fmt.Errorf("error processing an %s response: invalid length %d", responseType, len(payload))
+				return
+			}
+			responsePayload["receivedBytes"] = strconv.FormatUint(binary.LittleEndian.Uint64(payload), 10)
+			break
+		case messageType.Error:
+			var code uint16
+			var message string
+			code, message, err = decodeError(payload)
 			if err != nil {
-				err = fmt.Errorf("error processing a %s response: error downloading the %s from TCP stream: %s", responseType, matrix, err)
+				err = fmt.Errorf("error processing an %s response: %s", responseType, err)
 				return
 			}
 
-			responsePayload["matrixType"] = matrix.Type.String()
-			responsePayload["matrixDimension"] = strconv.FormatUint(uint64(matrix.Dimension), 10)
+			responsePayload["code"] = strconv.FormatUint(uint64(code), 10)
+			responsePayload["message"] = message
+			break
 		}
-		break
-	case messageType.Error:
-		_, err = con.Read(buffer[:])
-		if err != nil {
-			err = fmt.Errorf("error processing an %s response: error reading message length from TCP stream: %s", responseType, err)
+
+		request.Correlation = correlationId
+		resp = Response{
+			Client:      request.Client,
+			Correlation: correlationId,
+			Time:        time.Now(),
+			Kind:        kind.Response,
+			Type:        responseType,
+			Payload:     responsePayload,
+		}
+		if responseType != messageType.Upload {
 			return
 		}
-		len := buffer[0]
+	}
+	return
+}
+
+// a poll payload is a status code followed by the details of the status
+func readPollPayload(connection *Connection, id token.Token, payloadLen uint64, responsePayload map[string]string) error {
+	if payloadLen == 0 {
+		return fmt.Errorf("the payload is empty")
+	}
 
-		buffer := make([]uint8, len)
-		_, err = con.Read(buffer)
+	buffer := [1]uint8{}
+	_, err := io.ReadFull(connection.con, buffer[:])
+	if err != nil {
+		return fmt.Errorf("error reading status code from TCP stream: %s", err)
+	}
+	payloadLen -= 1
+
+	st, err := status.Decode(buffer[0])
+	if err != nil {
+		return err
+	}
+	responsePayload["status"] = st.String()
+
+	if st == status.Completed {
+		dimensions := [8]uint8{}
+		if payloadLen < uint64(len(dimensions)) {
+			return fmt.Errorf("the payload is too short: %d bytes", payloadLen+1)
+		}
+		_, err = io.ReadFull(connection.con, dimensions[:])
 		if err != nil {
-			err = fmt.Errorf("error processing ar %s response: error reading message from TCP stream: %s", responseType, err)
-			return
+			return fmt.Errorf("error reading matrix dimensions from TCP stream: %s", err)
 		}
+		payloadLen -= uint64(len(dimensions))
 
-		responsePayload["message"] = string(buffer)
-		break
+		matrix := matrices[id]
+		// a result that doesn't fit into a frame is left out and fetched in parts
+		if payloadLen == 0 && matrix.GetByteLen() > 0 {
+			err = fetchToFile(connection, id, matrix)
+		} else {
+			err = matrix.FromTCPStreamToFile(connection.con, payloadLen)
+		}
+		if err != nil {
+			return fmt.Errorf("error downloading the %s from TCP stream: %s", matrix, err)
+		}
+
+		responsePayload["matrixType"] = matrix.Type.String()
+		responsePayload["matrixRows"] = strconv.FormatUint(uint64(binary.LittleEndian.Uint32(dimensions[0:4])), 10)
+		responsePayload["matrixColumns"] = strconv.FormatUint(uint64(binary.LittleEndian.Uint32(dimensions[4:8])), 10)
+		return nil
 	}
 
-	resp = Response{
-		Client:  request.Client,
-		Time:    time.Now(),
-		Kind:    kind.Response,
-		Type:    responseType,
-		Payload: responsePayload,
+	details := make([]uint8, payloadLen)
+	_, err = io.ReadFull(connection.con, details)
+	if err != nil {
+		return fmt.Errorf("error reading from TCP stream: %s", err)
 	}
-	return
+
+	switch st {
+	case status.Reserved:
+		if len(details) == 16 {
+			responsePayload["receivedBytes"] = strconv.FormatUint(binary.LittleEndian.Uint64(details[0:8]), 10)
+			responsePayload["totalBytes"] = strconv.FormatUint(binary.LittleEndian.Uint64(details[8:16]), 10)
+		}
+	case status.Running:
+		if len(details) == 4 {
+			responsePayload["queueDepth"] = strconv.FormatUint(uint64(binary.LittleEndian.Uint32(details)), 10)
+		}
+	}
+	return nil
+}
+
+// a fetch request carries the job id, the unit, and 64-bit start and count
+const FETCH_REQUEST_LEN = token.LEN + 17
+
+// downloads the result of a transposition with fetch requests, a frame at a time
+func fetchToFile(connection *Connection, id token.Token, m matrix.Matrix) error {
+	file, err := m.CreateDownloadedFile(connection.con)
+	if err != nil {
+		return err
+	}
+	defer file.Close()
+
+	byteLen := m.GetByteLen()
+	for offset := uint64(0); offset < byteLen; {
+		buffer := [FETCH_REQUEST_LEN]uint8{}
+		copy(buffer[:token.LEN], id[:])
+		// the range is in bytes and cut short by the server to fit into a frame
+		buffer[token.LEN] = 0
+		binary.LittleEndian.PutUint64(buffer[token.LEN+1:token.LEN+9], offset)
+		binary.LittleEndian.PutUint64(buffer[token.LEN+9:], byteLen-offset)
+		correlationId, err := connection.send(messageType.Fetch, buffer[:])
+		if err != nil {
+			return fmt.Errorf("error sending a fetch request: %s", err)
+		}
+
+		responseType, payloadLen, err := connection.receive(correlationId)
+		if err != nil {
+			return fmt.Errorf("error reading a fetch response: %s", err)
+		}
+		if responseType != messageType.Fetch || payloadLen == 0 {
+			io.CopyN(io.Discard, connection.con, int64(payloadLen))
+			return fmt.Errorf("the server didn't send the bytes from %d on", offset)
+		}
+
+		_, err = io.CopyN(file, connection.con, int64(payloadLen))
+		if err != nil {
+			return fmt.Errorf("error downloading to file %s: %s", file.Name(), err)
+		}
+		offset += payloadLen
+	}
+	return nil
 }
 
 func (message Message) JsonString() string {
@@ -213,8 +399,8 @@ func (message Message) JsonString() string {
 		jsonPayload += fmt.Sprintf(`,"%s":"%s"`, key, value)
 	}
 
-	return fmt.Sprintf(`{"client":"%d","time":"%d","kind":"%s","type":"%s"%s}`,
-		message.Client, message.Time.UnixNano(), message.Kind, message.Type, jsonPayload)
+	return fmt.Sprintf(`{"client":"%d","correlation":"%d","time":"%d","kind":"%s","type":"%s"%s}`,
+		message.Client, message.Correlation, message.Time.UnixNano(), message.Kind, message.Type, jsonPayload)
 }
 
 func (request Request) JsonString() string {
@@ -9,6 +9,14 @@ const (
 	Calc
 	Poll
 	Error
+	Cancel
+	Upload
+	Resume
+	Fetch
+	Wait
+	Hello
+	Usage
+	Release
 )
 
 func (mType MessageType) String() string {
@@ -21,6 +29,22 @@ func (mType MessageType) String() string {
 		return "poll"
 	case Error:
 		return "error"
+	case Cancel:
+		return "cancel"
+	case Upload:
+		return "upload"
+	case Resume:
+		return "resume"
+	case Fetch:
+		return "fetch"
+	case Wait:
+		return "wait"
+	case Hello:
+		return "hello"
+	case Usage:
+		return "usage"
+	case Release:
+		return "release"
 	}
 
 	return "undefined"
@@ -31,7 +55,7 @@ func (mType MessageType) Encode() uint8 {
 }
 
 func Decode(code uint8) (mType MessageType, err error) {
-	if code > uint8(Error) {
+	if code > uint8(Release) {
 		err = fmt.Errorf("Unknown message type code: %d", code)
 		return
 	}
@@ -6,28 +6,31 @@ type Status uint8
 
 const (
 	NoData Status = iota
-	Ready
+	Reserved
 	Running
 	Completed
+	Cancelled
 )
 
 func (status Status) String() string {
 	switch status {
 	case NoData:
 		return "noData"
-	case Ready:
-		return "ready"
+	case Reserved:
+		return "reserved"
 	case Running:
 		return "running"
 	case Completed:
 		return "completed"
+	case Cancelled:
+		return "cancelled"
 	}
 
 	return "unknown"
 }
 
 func Decode(code uint8) (status Status, err error) {
-	if code > uint8(Completed) {
+	if code > uint8(Cancelled) {
 		err = fmt.Errorf("Unknown status code: %d", code)
 		return
 	}
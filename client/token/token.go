@@ -0,0 +1,40 @@
+package token
+
+import (
+	"encoding/hex"
+	"fmt"
+)
+
+const LEN = 16
+
+// a job id assigned by the server. It's sent as a 128-bit little-endian number
+// and printed as 32 hex digits, most significant first, the way the server
+// prints it
+type Token [LEN]uint8
+
+func (token Token) String() string {
+	reversed := make([]uint8, LEN)
+	for i, b := range token {
+		reversed[LEN-1-i] = b
+	}
+
+	return hex.EncodeToString(reversed)
+}
+
+func FromString(str string) (token Token, err error) {
+	decoded, err := hex.DecodeString(str)
+	if err != nil {
+		err = fmt.Errorf("Invalid job id %s: %s", str, err)
+		return
+	}
+
+	if len(decoded) != LEN {
+		err = fmt.Errorf("Invalid job id %s: must be %d hex digits", str, 2*LEN)
+		return
+	}
+
+	for i, b := range decoded {
+		token[LEN-1-i] = b
+	}
+	return
+}
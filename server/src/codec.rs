@@ -0,0 +1,449 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    error::{Error, ErrorCode},
+    request::Request,
+    response::Response,
+    token::Token,
+};
+
+// the payload length (8 bytes), the message code (1 byte) and the correlation ID (4 bytes)
+pub const HEADER_LEN: usize = 13;
+
+pub struct Frame {
+    pub code: u8,
+    pub correlation_id: u32,
+    pub payload: Bytes,
+}
+
+// the header of a frame whose payload is over the limit
+#[derive(Debug)]
+pub struct OversizedFrame {
+    pub correlation_id: u32,
+    pub payload_len: u64,
+}
+
+/// Splits the next complete frame off the front of the buffer, or returns `None`
+/// if the buffer doesn't hold a complete frame yet. The header of an oversized
+/// frame is consumed, its payload is left for the caller to skip.
+pub fn decode_frame(
+    src: &mut BytesMut,
+    max_payload_len: u64,
+) -> Result<Option<Frame>, OversizedFrame> {
+    if src.len() < HEADER_LEN {
+        return Ok(None);
+    }
+
+    let payload_len = u64::from_le_bytes(src[..8].try_into().unwrap());
+    // a frame too long to be addressed is over any limit
+    let frame_len = usize::try_from(payload_len)
+        .ok()
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .filter(|_| payload_len <= max_payload_len);
+    let Some(frame_len) = frame_len else {
+        let mut header = src.split_to(HEADER_LEN);
+        header.advance(9);
+        return Err(OversizedFrame {
+            correlation_id: header.get_u32_le(),
+            payload_len,
+        });
+    };
+    if src.len() < frame_len {
+        return Ok(None);
+    }
+
+    let mut frame = src.split_to(frame_len).freeze();
+    frame.advance(8);
+    let code = frame.get_u8();
+    let correlation_id = frame.get_u32_le();
+    Ok(Some(Frame {
+        code,
+        correlation_id,
+        payload: frame,
+    }))
+}
+
+/// Appends a frame to the buffer, `write_payload` writes the payload after the header.
+pub fn encode_frame(
+    code: u8,
+    correlation_id: u32,
+    dst: &mut BytesMut,
+    write_payload: impl FnOnce(&mut BytesMut),
+) {
+    let start = dst.len();
+    dst.put_u64_le(0);
+    dst.put_u8(code);
+    dst.put_u32_le(correlation_id);
+    write_payload(dst);
+
+    let payload_len = (dst.len() - start - HEADER_LEN) as u64;
+    dst[start..start + 8].copy_from_slice(&payload_len.to_le_bytes());
+}
+
+fn ensure_remaining(payload: &Bytes, len: usize) -> Result<(), Error> {
+    if payload.remaining() < len {
+        return Err(Error::new(
+            ErrorCode::MalformedMessage,
+            "the message is too short",
+        ));
+    }
+    Ok(())
+}
+
+pub fn read_u8(payload: &mut Bytes) -> Result<u8, Error> {
+    ensure_remaining(payload, 1)?;
+    Ok(payload.get_u8())
+}
+
+pub fn read_u16(payload: &mut Bytes) -> Result<u16, Error> {
+    ensure_remaining(payload, 2)?;
+    Ok(payload.get_u16_le())
+}
+
+pub fn read_u32(payload: &mut Bytes) -> Result<u32, Error> {
+    ensure_remaining(payload, 4)?;
+    Ok(payload.get_u32_le())
+}
+
+pub fn read_u64(payload: &mut Bytes) -> Result<u64, Error> {
+    ensure_remaining(payload, 8)?;
+    Ok(payload.get_u64_le())
+}
+
+pub fn read_token(payload: &mut Bytes) -> Result<Token, Error> {
+    ensure_remaining(payload, Token::LEN)?;
+    let mut buffer = [0u8; Token::LEN];
+    payload.copy_to_slice(&mut buffer);
+    Ok(Token::from_le_bytes(buffer))
+}
+
+// checks that the whole payload has been parsed
+pub fn read_end(payload: &Bytes) -> Result<(), Error> {
+    if payload.has_remaining() {
+        return Err(Error::new(
+            ErrorCode::MalformedMessage,
+            "the message is too long",
+        ));
+    }
+    Ok(())
+}
+
+/// Decodes requests and encodes responses. A frame with a malformed or oversized
+/// payload is decoded into an error instead of breaking the stream.
+pub struct ServerCodec {
+    max_payload_len: u64,
+    // the bytes of an oversized payload that still have to be skipped
+    discarding: u64,
+}
+
+impl ServerCodec {
+    pub fn new(max_payload_len: u64) -> ServerCodec {
+        ServerCodec {
+            max_payload_len,
+            discarding: 0,
+        }
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = (u32, Result<Request, Error>);
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if self.discarding > 0 {
+            let len = self.discarding.min(src.len() as u64);
+            src.advance(len as usize);
+            self.discarding -= len;
+            if self.discarding > 0 {
+                return Ok(None);
+            }
+        }
+
+        match decode_frame(src, self.max_payload_len) {
+            Ok(frame) => Ok(frame.map(|frame| {
+                (
+                    frame.correlation_id,
+                    Request::decode(frame.code, frame.payload),
+                )
+            })),
+            Err(frame) => {
+                self.discarding = frame.payload_len;
+                let error = Error::new(
+                    ErrorCode::TooLarge,
+                    format!(
+                        "the message payload is {} bytes long, the limit is {} bytes",
+                        frame.payload_len, self.max_payload_len
+                    ),
+                );
+                Ok(Some((frame.correlation_id, Err(error))))
+            }
+        }
+    }
+}
+
+impl Encoder<(u32, Response)> for ServerCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: (u32, Response), dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let (correlation_id, response) = item;
+        response.encode(correlation_id, dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::{
+        job::FetchUnit,
+        matrix_type::MatrixType,
+        operation::{Arithmetic, Conversion, Operation, Product, Reduction},
+        quota::Usage,
+        status::Status,
+    };
+
+    fn requests() -> Vec<Request> {
+        let id = Token::generate();
+        vec![
+            Request::Reserve {
+                matrix_type: MatrixType::F64,
+                matrix_rows: 3,
+                matrix_columns: u32::MAX,
+                priority: 7,
+                operation: Operation::Rotate270,
+                conversion: Some(Conversion {
+                    matrix_type: MatrixType::U8,
+                    arithmetic: Arithmetic::Wrapping,
+                }),
+            },
+            Request::Reserve {
+                matrix_type: MatrixType::I8,
+                matrix_rows: 1,
+                matrix_columns: 1,
+                priority: 0,
+                operation: Operation::Reduce(Reduction::ColumnSums),
+                conversion: None,
+            },
+            Request::Reserve {
+                matrix_type: MatrixType::I32,
+                matrix_rows: 4,
+                matrix_columns: 5,
+                priority: 0,
+                operation: Operation::Multiply(Product {
+                    columns: 6,
+                    arithmetic: Arithmetic::Saturating,
+                }),
+                conversion: Some(Conversion {
+                    matrix_type: MatrixType::F32,
+                    arithmetic: Arithmetic::Saturating,
+                }),
+            },
+            Request::Calc {
+                id,
+                matrix: Bytes::from_static(&[1, 2, 3, 4]),
+            },
+            Request::Poll { id },
+            Request::Cancel { id },
+            Request::Upload {
+                id,
+                offset: 1 << 40,
+                chunk: Bytes::from_static(&[5, 6, 7]),
+            },
+            Request::Resume { id },
+            Request::Fetch {
+                id,
+                unit: FetchUnit::Rows,
+                start: 2,
+                count: u64::MAX,
+            },
+            Request::Wait {
+                id,
+                timeout_ms: 100,
+            },
+            Request::Hello {
+                protocol_version: 1,
+                client_name: None,
+            },
+            Request::Hello {
+                protocol_version: 1,
+                client_name: Some(String::from("client")),
+            },
+            Request::Usage,
+            Request::Release { id },
+        ]
+    }
+
+    fn responses() -> Vec<Response> {
+        vec![
+            Response::Reserve {
+                id: Token::generate(),
+            },
+            Response::Calc,
+            Response::Poll {
+                status: Status::Reserved {
+                    received_bytes: 10,
+                    total_bytes: 20,
+                },
+            },
+            Response::Poll {
+                status: Status::Running { queue_depth: 3 },
+            },
+            Response::Poll {
+                status: Status::Completed {
+                    matrix_rows: 1,
+                    matrix_columns: 2,
+                    matrix_bytes: Bytes::from_static(&[1, 2]),
+                },
+            },
+            Response::Error {
+                error: Error::new(ErrorCode::NotEnoughMemory, "not enough memory"),
+            },
+            Response::Error {
+                error: Error::new(ErrorCode::ServerBusy, "busy")
+                    .with_retry_after(Duration::from_millis(1500)),
+            },
+            // longer than the one byte length errors used to have
+            Response::Error {
+                error: Error::new(ErrorCode::InvalidArgument, "é".repeat(300)),
+            },
+            Response::Cancel,
+            Response::Upload { received_bytes: 3 },
+            Response::Resume { committed_bytes: 4 },
+            Response::Fetch {
+                matrix_bytes: Bytes::from_static(&[9, 8, 7]),
+            },
+            Response::Wait {
+                status: Status::Cancelled,
+            },
+            Response::Hello {
+                protocol_version: 2,
+                matrix_types: 0x3ff,
+                max_dimension: u32::MAX,
+                max_frame_len: 1 << 24,
+                request_codes: 0xff7,
+                operations: 0x1_ffff,
+                capabilities: 0x7,
+            },
+            Response::Usage {
+                usage: Usage {
+                    jobs: 1,
+                    max_jobs: 2,
+                    bytes: 3,
+                    max_bytes: u64::MAX,
+                    running: 4,
+                    max_running: 5,
+                },
+            },
+            Response::Release,
+        ]
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        for (correlation_id, request) in requests().into_iter().enumerate() {
+            let mut buffer = BytesMut::new();
+            request.encode(correlation_id as u32, &mut buffer);
+
+            let frame = decode_frame(&mut buffer, u64::MAX).unwrap().unwrap();
+            assert!(buffer.is_empty());
+            assert_eq!(frame.correlation_id, correlation_id as u32);
+            assert_eq!(Request::decode(frame.code, frame.payload).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        for (correlation_id, response) in responses().into_iter().enumerate() {
+            let mut buffer = BytesMut::new();
+            ServerCodec::new(u64::MAX)
+                .encode((correlation_id as u32, response.clone()), &mut buffer)
+                .unwrap();
+
+            let frame = decode_frame(&mut buffer, u64::MAX).unwrap().unwrap();
+            assert!(buffer.is_empty());
+            assert_eq!(frame.correlation_id, correlation_id as u32);
+            assert_eq!(
+                Response::decode(frame.code, frame.payload).unwrap(),
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_frames_split_at_every_byte() {
+        let requests = requests();
+        let mut encoded = BytesMut::new();
+        for (correlation_id, request) in requests.iter().enumerate() {
+            request.encode(correlation_id as u32, &mut encoded);
+        }
+
+        let mut codec = ServerCodec::new(u64::MAX);
+        let mut buffer = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in encoded {
+            buffer.put_u8(byte);
+            while let Some((_, request)) = codec.decode(&mut buffer).unwrap() {
+                decoded.push(request.unwrap());
+            }
+        }
+        assert_eq!(decoded, requests);
+    }
+
+    #[test]
+    fn rejects_malformed_payloads_without_losing_the_stream() {
+        let mut buffer = BytesMut::new();
+        encode_frame(2, 1, &mut buffer, |payload| payload.put_u8(0));
+        encode_frame(42, 2, &mut buffer, |_| ());
+        encode_frame(4, 3, &mut buffer, |payload| {
+            payload.put_slice(&[0; Token::LEN + 1])
+        });
+        Request::Poll {
+            id: Token::generate(),
+        }
+        .encode(4, &mut buffer);
+
+        let mut codec = ServerCodec::new(u64::MAX);
+        let mut decoded = Vec::new();
+        while let Some((correlation_id, request)) = codec.decode(&mut buffer).unwrap() {
+            decoded.push((correlation_id, request.is_ok()));
+        }
+        assert_eq!(decoded, vec![(1, false), (2, false), (3, false), (4, true)]);
+    }
+
+    #[test]
+    fn skips_frames_over_the_limit() {
+        let mut buffer = BytesMut::new();
+        encode_frame(1, 1, &mut buffer, |payload| payload.put_slice(&[0; 100]));
+        assert!(decode_frame(&mut buffer.clone(), 100).unwrap().is_some());
+
+        Request::Poll {
+            id: Token::generate(),
+        }
+        .encode(2, &mut buffer);
+        let mut codec = ServerCodec::new(99);
+        let mut decoded = Vec::new();
+        let mut src = BytesMut::new();
+        // the oversized payload arrives in pieces
+        for piece in buffer.chunks(7) {
+            src.put_slice(piece);
+            while let Some((correlation_id, request)) = codec.decode(&mut src).unwrap() {
+                decoded.push((correlation_id, request.map(|_| ()).map_err(|e| e.code)));
+            }
+        }
+        assert_eq!(decoded, vec![(1, Err(ErrorCode::TooLarge)), (2, Ok(()))]);
+
+        // the length of the whole frame doesn't fit into a usize
+        let mut buffer = BytesMut::new();
+        buffer.put_u64_le(u64::MAX - 5);
+        buffer.put_u8(2);
+        buffer.put_u32_le(3);
+        let Err(frame) = decode_frame(&mut buffer, u64::MAX) else {
+            panic!("a frame over any limit was decoded");
+        };
+        assert_eq!((frame.correlation_id, frame.payload_len), (3, u64::MAX - 5));
+        assert!(buffer.is_empty());
+    }
+}
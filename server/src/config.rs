@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+
+use crate::{limits::JobLimits, memory::MemoryCap, quota::Quota, scheduler::Policy};
+
+pub struct Config {
+    pub port: String,
+    pub max_concurrent_jobs: Option<usize>,
+    pub upload_grace_period: Duration,
+    pub result_ttl: Duration,
+    pub max_frame_len: u64,
+    pub memory_cap: MemoryCap,
+    pub max_dimension: u32,
+    pub max_job_bytes: u64,
+    pub quota: Quota,
+    pub scheduling: Policy,
+    pub max_queued_jobs: usize,
+    // requests of one connection that are executed or waiting to be written
+    // back, the connection isn't read any further until one of them is answered
+    pub max_in_flight_requests: usize,
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let mut config = Config {
+            port: String::from("0"),
+            max_concurrent_jobs: None,
+            upload_grace_period: Duration::from_secs(600),
+            result_ttl: Duration::from_secs(3600),
+            // a larger matrix is uploaded and its result fetched in parts of about this size
+            max_frame_len: 16 * 1024 * 1024,
+            memory_cap: MemoryCap::Percent(80),
+            max_dimension: 1 << 20,
+            max_job_bytes: 16 * 1024 * 1024 * 1024,
+            quota: Quota::UNLIMITED,
+            scheduling: Policy::FairShare,
+            max_queued_jobs: 256,
+            max_in_flight_requests: 64,
+        };
+
+        let mut port_set = false;
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(option) => {
+                    let (key, value) = option
+                        .split_once('=')
+                        .ok_or(format!("options must be passed as --key=value: {arg}"))?;
+                    match key {
+                        "max-concurrent-jobs" => {
+                            let value = parse_number(key, value)?;
+                            if value == 0 {
+                                Err("max-concurrent-jobs must be at least 1")?
+                            }
+                            config.max_concurrent_jobs = Some(value);
+                        }
+                        "upload-grace-period" => {
+                            config.upload_grace_period =
+                                Duration::from_secs(parse_number(key, value)? as u64);
+                        }
+                        "result-ttl" => {
+                            config.result_ttl =
+                                Duration::from_secs(parse_number(key, value)? as u64);
+                        }
+                        "max-frame-len" => {
+                            config.max_frame_len = parse_number(key, value)? as u64;
+                        }
+                        "memory-cap" => {
+                            config.memory_cap = value
+                                .parse()
+                                .map_err(|e| format!("invalid value for {key}: {e}"))?;
+                        }
+                        "max-dimension" => {
+                            let value = parse_u32(key, value)?;
+                            if value == 0 {
+                                Err("max-dimension must be at least 1")?
+                            }
+                            config.max_dimension = value;
+                        }
+                        "max-job-bytes" => {
+                            config.max_job_bytes = parse_number(key, value)? as u64;
+                        }
+                        "max-jobs-per-client" => {
+                            config.quota.max_jobs = parse_u32(key, value)?;
+                        }
+                        "max-bytes-per-client" => {
+                            config.quota.max_bytes = parse_number(key, value)? as u64;
+                        }
+                        "max-running-per-client" => {
+                            config.quota.max_running = parse_u32(key, value)?;
+                        }
+                        "max-queued-jobs" => {
+                            let value = parse_number(key, value)?;
+                            if value == 0 {
+                                Err("max-queued-jobs must be at least 1")?
+                            }
+                            if value > Semaphore::MAX_PERMITS {
+                                Err(format!(
+                                    "max-queued-jobs must be at most {}",
+                                    Semaphore::MAX_PERMITS
+                                ))?
+                            }
+                            config.max_queued_jobs = value;
+                        }
+                        "max-in-flight-requests" => {
+                            let value = parse_number(key, value)?;
+                            if value == 0 {
+                                Err("max-in-flight-requests must be at least 1")?
+                            }
+                            config.max_in_flight_requests = value;
+                        }
+                        "scheduling" => config.scheduling = value.parse()?,
+                        _ => Err(format!("unknown option: {key}"))?,
+                    }
+                }
+                None if !port_set => {
+                    config.port = arg.clone();
+                    port_set = true;
+                }
+                None => Err("there can be only one positional argument: the port number")?,
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn job_limits(&self) -> JobLimits {
+        JobLimits {
+            max_dimension: self.max_dimension,
+            max_job_bytes: self.max_job_bytes,
+        }
+    }
+}
+
+fn parse_number(key: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|e| format!("invalid value for {key}: {e}"))
+}
+
+fn parse_u32(key: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|e| format!("invalid value for {key}: {e}"))
+}
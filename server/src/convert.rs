@@ -0,0 +1,259 @@
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    matrix_type::{with_element, Element, MatrixType},
+    operation::{Arithmetic, Conversion},
+};
+
+// elements per task, enough to outweigh the cost of scheduling it
+const CHUNK_ELEMENTS: usize = 1 << 14;
+
+// every element fits exactly into one of these on its way to the other type
+#[derive(Clone, Copy)]
+enum Value {
+    Integer(i128),
+    Float(f64),
+}
+
+trait Convert: Element {
+    fn value(self) -> Value;
+    fn from_value(value: Value, arithmetic: Arithmetic) -> Self;
+}
+
+macro_rules! integer {
+    ($($element:ty),*) => {$(
+        impl Convert for $element {
+            fn value(self) -> Value {
+                Value::Integer(self as i128)
+            }
+
+            fn from_value(value: Value, arithmetic: Arithmetic) -> Self {
+                match (value, arithmetic) {
+                    // only the low bits are kept
+                    (Value::Integer(value), Arithmetic::Wrapping) => value as $element,
+                    (Value::Integer(value), Arithmetic::Saturating) => {
+                        value.clamp(<$element>::MIN as i128, <$element>::MAX as i128) as $element
+                    }
+                    // rounded to the nearest integer, ties to even. A float always
+                    // saturates, and NaN becomes 0
+                    (Value::Float(value), _) => value.round_ties_even() as $element,
+                }
+            }
+        }
+    )*};
+}
+
+macro_rules! float {
+    ($($element:ty),*) => {$(
+        impl Convert for $element {
+            fn value(self) -> Value {
+                Value::Float(self as f64)
+            }
+
+            // rounded to the nearest float, ties to even, and past the largest
+            // float to infinity
+            fn from_value(value: Value, _: Arithmetic) -> Self {
+                match value {
+                    Value::Integer(value) => value as $element,
+                    Value::Float(value) => value as $element,
+                }
+            }
+        }
+    )*};
+}
+
+integer!(u8, u16, u32, u64, i8, i16, i32, i64);
+float!(f32, f64);
+
+/// Converts every element of a matrix of `from` elements to the type of the
+/// conversion. Must be called from inside the thread pool. A cancelled
+/// conversion stops early and returns an incomplete matrix.
+pub fn convert(
+    from: MatrixType,
+    conversion: Conversion,
+    matrix_vec: &[u8],
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    with_element!(from, F => with_element!(conversion.matrix_type, T => {
+        convert_typed::<F, T>(matrix_vec, conversion.arithmetic, cancelled)
+    }))
+}
+
+fn convert_typed<F: Convert, T: Convert>(
+    matrix_vec: &[u8],
+    arithmetic: Arithmetic,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    let mut converted_vec = vec![0u8; matrix_vec.len() / F::SIZE * T::SIZE];
+    let _ = converted_vec
+        .par_chunks_mut(CHUNK_ELEMENTS * T::SIZE)
+        .zip(matrix_vec.par_chunks(CHUNK_ELEMENTS * F::SIZE))
+        .try_for_each(|(dst_chunk, src_chunk)| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            let elements = dst_chunk
+                .chunks_exact_mut(T::SIZE)
+                .zip(src_chunk.chunks_exact(F::SIZE));
+            for (dst, src) in elements {
+                T::from_value(F::read(src).value(), arithmetic).write(dst);
+            }
+            Ok(())
+        });
+    converted_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_bytes<E: Element>(elements: &[E]) -> Vec<u8> {
+        let mut bytes = vec![0u8; elements.len() * E::SIZE];
+        for (element, dst) in elements.iter().zip(bytes.chunks_exact_mut(E::SIZE)) {
+            element.write(dst);
+        }
+        bytes
+    }
+
+    fn convert_all<F: Element, T: Element>(
+        from: MatrixType,
+        to: MatrixType,
+        arithmetic: Arithmetic,
+        elements: &[F],
+    ) -> Vec<T> {
+        let cancelled = AtomicBool::new(false);
+        let conversion = Conversion {
+            matrix_type: to,
+            arithmetic,
+        };
+        let converted_vec = convert(from, conversion, &to_bytes(elements), &cancelled);
+        converted_vec.chunks_exact(T::SIZE).map(T::read).collect()
+    }
+
+    #[test]
+    fn converts_between_every_pair_of_types() {
+        // more elements than fit into a single chunk
+        let elements: Vec<u8> = (0..CHUNK_ELEMENTS + 10).map(|i| (i % 100) as u8).collect();
+        for from in MatrixType::ALL {
+            for to in MatrixType::ALL {
+                for arithmetic in [Arithmetic::Wrapping, Arithmetic::Saturating] {
+                    let saturating = Conversion {
+                        matrix_type: from,
+                        arithmetic: Arithmetic::Saturating,
+                    };
+                    let cancelled = AtomicBool::new(false);
+                    let matrix_vec = convert(MatrixType::U8, saturating, &elements, &cancelled);
+
+                    // small whole numbers are the same in every type
+                    let conversion = Conversion {
+                        matrix_type: to,
+                        arithmetic,
+                    };
+                    let converted_vec = convert(from, conversion, &matrix_vec, &cancelled);
+                    assert_eq!(
+                        converted_vec.len(),
+                        elements.len() * to.get_type_size() as usize
+                    );
+                    let back = convert(to, saturating, &converted_vec, &cancelled);
+                    assert_eq!(
+                        back,
+                        matrix_vec,
+                        "{} to {} and back",
+                        String::from(from),
+                        String::from(to)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn integers_wrap_or_saturate() {
+        use Arithmetic::{Saturating, Wrapping};
+        use MatrixType::{I16, I32, I64, I8, U16, U64, U8};
+
+        let elements = [0, 100, 255u8];
+        assert_eq!(
+            convert_all::<_, i8>(U8, I8, Wrapping, &elements),
+            [0, 100, -1]
+        );
+        assert_eq!(
+            convert_all::<_, i8>(U8, I8, Saturating, &elements),
+            [0, 100, 127]
+        );
+
+        let elements = [-1, 300i16];
+        assert_eq!(
+            convert_all::<_, u16>(I16, U16, Wrapping, &elements),
+            [u16::MAX, 300]
+        );
+        assert_eq!(
+            convert_all::<_, u16>(I16, U16, Saturating, &elements),
+            [0, 300]
+        );
+        assert_eq!(
+            convert_all::<_, u8>(I16, U8, Wrapping, &elements),
+            [255, 44]
+        );
+        assert_eq!(
+            convert_all::<_, u8>(I16, U8, Saturating, &elements),
+            [0, 255]
+        );
+
+        let elements = [i64::MIN, -5];
+        assert_eq!(
+            convert_all::<_, i32>(I64, I32, Wrapping, &elements),
+            [0, -5]
+        );
+        assert_eq!(
+            convert_all::<_, i32>(I64, I32, Saturating, &elements),
+            [i32::MIN, -5]
+        );
+
+        let elements = [u64::MAX];
+        assert_eq!(convert_all::<_, i64>(U64, I64, Wrapping, &elements), [-1]);
+        assert_eq!(
+            convert_all::<_, i64>(U64, I64, Saturating, &elements),
+            [i64::MAX]
+        );
+    }
+
+    #[test]
+    fn floats_are_rounded() {
+        use Arithmetic::{Saturating, Wrapping};
+        use MatrixType::{F32, F64, I32, I64, U64, U8};
+
+        // ties go to the even neighbour
+        let elements = [2.5, 3.5, -2.5, 0.4999, 1e10, f32::NEG_INFINITY, f32::NAN];
+        let expected = [2, 4, -2, 0, i32::MAX, i32::MIN, 0];
+        assert_eq!(
+            convert_all::<_, i32>(F32, I32, Saturating, &elements),
+            expected
+        );
+        // floats saturate even with wrapping arithmetic
+        assert_eq!(
+            convert_all::<_, i32>(F32, I32, Wrapping, &elements),
+            expected
+        );
+        assert_eq!(
+            convert_all::<_, u8>(F64, U8, Wrapping, &[-3.0, 255.6, 254.5]),
+            [0, 255, 254]
+        );
+
+        assert_eq!(
+            convert_all::<_, f32>(U64, F32, Saturating, &[u64::MAX]),
+            [18446744073709551616.0]
+        );
+        let elements = [(1i64 << 53) + 1, (1 << 53) + 3];
+        assert_eq!(
+            convert_all::<_, f64>(I64, F64, Saturating, &elements),
+            [(1u64 << 53) as f64, ((1u64 << 53) + 4) as f64]
+        );
+        assert_eq!(
+            convert_all::<_, f32>(F64, F32, Saturating, &[1e40, 0.1]),
+            [f32::INFINITY, 0.1]
+        );
+    }
+}
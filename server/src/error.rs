@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    NotEnoughMemory,
+    UnknownId,
+    BadTypeCode,
+    InvalidState,
+    TooLarge,
+    MalformedMessage,
+    UnknownRequest,
+    InvalidArgument,
+    UnsupportedVersion,
+    // the request isn't allowed at this point of the conversation
+    ProtocolViolation,
+    DuplicateCorrelationId,
+    QuotaExceeded,
+    // the client may try again later
+    ServerBusy,
+}
+
+impl std::convert::From<ErrorCode> for u16 {
+    fn from(value: ErrorCode) -> Self {
+        match value {
+            ErrorCode::NotEnoughMemory => 0,
+            ErrorCode::UnknownId => 1,
+            ErrorCode::BadTypeCode => 2,
+            ErrorCode::InvalidState => 3,
+            ErrorCode::TooLarge => 4,
+            ErrorCode::MalformedMessage => 5,
+            ErrorCode::UnknownRequest => 6,
+            ErrorCode::InvalidArgument => 7,
+            ErrorCode::UnsupportedVersion => 8,
+            ErrorCode::ProtocolViolation => 9,
+            ErrorCode::DuplicateCorrelationId => 10,
+            ErrorCode::QuotaExceeded => 11,
+            ErrorCode::ServerBusy => 12,
+        }
+    }
+}
+
+impl std::convert::TryFrom<u16> for ErrorCode {
+    type Error = Error;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ErrorCode::NotEnoughMemory),
+            1 => Ok(ErrorCode::UnknownId),
+            2 => Ok(ErrorCode::BadTypeCode),
+            3 => Ok(ErrorCode::InvalidState),
+            4 => Ok(ErrorCode::TooLarge),
+            5 => Ok(ErrorCode::MalformedMessage),
+            6 => Ok(ErrorCode::UnknownRequest),
+            7 => Ok(ErrorCode::InvalidArgument),
+            8 => Ok(ErrorCode::UnsupportedVersion),
+            9 => Ok(ErrorCode::ProtocolViolation),
+            10 => Ok(ErrorCode::DuplicateCorrelationId),
+            11 => Ok(ErrorCode::QuotaExceeded),
+            12 => Ok(ErrorCode::ServerBusy),
+            _ => Err(Error::new(
+                ErrorCode::MalformedMessage,
+                format!("Invalid error code: {}", value),
+            )),
+        }
+    }
+}
+
+impl std::convert::From<ErrorCode> for String {
+    fn from(value: ErrorCode) -> Self {
+        match value {
+            ErrorCode::NotEnoughMemory => String::from("not enough memory"),
+            ErrorCode::UnknownId => String::from("unknown id"),
+            ErrorCode::BadTypeCode => String::from("bad type code"),
+            ErrorCode::InvalidState => String::from("invalid state"),
+            ErrorCode::TooLarge => String::from("too large"),
+            ErrorCode::MalformedMessage => String::from("malformed message"),
+            ErrorCode::UnknownRequest => String::from("unknown request"),
+            ErrorCode::InvalidArgument => String::from("invalid argument"),
+            ErrorCode::UnsupportedVersion => String::from("unsupported version"),
+            ErrorCode::ProtocolViolation => String::from("protocol violation"),
+            ErrorCode::DuplicateCorrelationId => String::from("duplicate correlation id"),
+            ErrorCode::QuotaExceeded => String::from("quota exceeded"),
+            ErrorCode::ServerBusy => String::from("server busy"),
+        }
+    }
+}
+
+/// An error that is sent to the client: a code to branch on and a message
+/// for humans.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+    // when it's worth trying again, only sent with server busy errors
+    pub retry_after: Option<Duration>,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Error {
+        Error {
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn with_retry_after(self, retry_after: Duration) -> Error {
+        Error {
+            retry_after: Some(retry_after),
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", String::from(self.code), self.message)
+    }
+}
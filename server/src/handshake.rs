@@ -0,0 +1,110 @@
+use crate::{
+    config::Config,
+    error::{Error, ErrorCode},
+    matrix_type::MatrixType,
+    operation::{Operation, Reduction},
+    response::Response,
+};
+
+// the protocol versions this server can speak. The frame header never changes
+// between versions, so a hello can always be read. Version 2 sends errors with a
+// 16-bit code and a 32-bit message length, the queue depth with a running status
+// and the capability mask with the hello response. A version 1 client would
+// misread them, so it's refused
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+pub const PROTOCOL_VERSION: u16 = 2;
+
+// the optional fields a request may end with. They don't change what the older
+// clients send or receive, so they're announced in the hello response instead
+// of taking a new version
+const CAPABILITY_PRIORITY: u32 = 1 << 0;
+const CAPABILITY_OPERATION: u32 = 1 << 1;
+// the result type and arithmetic after the operation
+const CAPABILITY_CONVERSION: u32 = 1 << 2;
+const CAPABILITIES: u32 = CAPABILITY_PRIORITY | CAPABILITY_OPERATION | CAPABILITY_CONVERSION;
+
+// the codes of the requests this server understands
+const REQUEST_CODES: [u8; 11] = [0, 1, 2, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const MAX_CLIENT_NAME_LEN: usize = 255;
+
+/// Answers the hello a connection must start with. The server speaks the version
+/// the client asked for, or refuses the connection if it can't.
+pub fn hello(
+    protocol_version: u16,
+    client_name: Option<&str>,
+    config: &Config,
+) -> Result<Response, Error> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        return Err(Error::new(
+            ErrorCode::UnsupportedVersion,
+            format!("unsupported protocol version {protocol_version}, the server supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"),
+        ));
+    }
+    if client_name.is_some_and(|name| name.len() > MAX_CLIENT_NAME_LEN) {
+        return Err(Error::new(
+            ErrorCode::InvalidArgument,
+            format!("the client name can be at most {MAX_CLIENT_NAME_LEN} bytes long"),
+        ));
+    }
+
+    Ok(Response::Hello {
+        protocol_version,
+        matrix_types: MatrixType::ALL
+            .iter()
+            .fold(0, |mask, &matrix_type| mask | 1 << u8::from(matrix_type)),
+        max_dimension: config.max_dimension,
+        max_frame_len: config.max_frame_len,
+        request_codes: REQUEST_CODES.iter().fold(0, |mask, code| mask | 1 << code),
+        operations: Operation::UNARY
+            .into_iter()
+            .chain(Reduction::ALL.map(Operation::Reduce))
+            .map(u8::from)
+            .chain([Operation::MULTIPLY])
+            .fold(0, |mask, code| mask | 1 << code),
+        capabilities: CAPABILITIES,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_unsupported_versions() {
+        let config = Config::from_args(&[]).unwrap();
+
+        assert!(hello(MIN_PROTOCOL_VERSION - 1, None, &config).is_err());
+        assert!(hello(PROTOCOL_VERSION + 1, None, &config).is_err());
+        match hello(PROTOCOL_VERSION, None, &config) {
+            Ok(Response::Hello {
+                protocol_version,
+                matrix_types,
+                max_dimension,
+                request_codes,
+                operations,
+                capabilities,
+                ..
+            }) => {
+                assert_eq!(protocol_version, PROTOCOL_VERSION);
+                assert_eq!(max_dimension, config.max_dimension);
+                assert_eq!(matrix_types, 0b11_1111_1111);
+                assert_eq!(request_codes, 0b1111_1111_0111);
+                assert_eq!(operations, 0b1_1111_1111_1111_1111);
+                assert_eq!(capabilities, 0b111);
+            }
+            response => panic!("unexpected response: {response:?}"),
+        }
+    }
+
+    #[test]
+    fn refuses_long_client_names() {
+        let config = Config::from_args(&[]).unwrap();
+
+        let name = "a".repeat(MAX_CLIENT_NAME_LEN);
+        assert!(hello(PROTOCOL_VERSION, Some(&name), &config).is_ok());
+        let name = "a".repeat(MAX_CLIENT_NAME_LEN + 1);
+        let error = hello(PROTOCOL_VERSION, Some(&name), &config).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+    }
+}
@@ -1,153 +1,451 @@
 use std::future::Future;
-use rayon::prelude::*;
+use futures::stream::{FuturesUnordered, StreamExt};
 use tokio::sync::oneshot::Receiver;
 use std::pin::Pin;
 use std::task::Poll;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
 
-use once_cell::sync::Lazy;
-use sysinfo::{RefreshKind, System, SystemExt};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use bytes::Bytes;
+use serde::Serialize;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::Notify;
 
-use crate::{matrix_type::MatrixType, status::Status};
-use itertools::Itertools;
+use crate::{
+    convert,
+    error::{Error, ErrorCode},
+    kernel,
+    limits::JobLimits,
+    matmul,
+    matrix_type::MatrixType,
+    memory::{MemoryLedger, MemoryReservation},
+    operation::{Conversion, Operation},
+    queue::{JobQueue, QueueSlot},
+    quota::{ClientId, JobQuota, QuotaLedger, Usage},
+    reduce,
+    scheduler::{Policy, Scheduler},
+    status::{self, Status},
+    token::Token,
+    upload::ReceivedRanges,
+};
 
-static SYSTEM: once_cell::sync::Lazy<tokio::sync::Mutex<sysinfo::System>> =
-    Lazy::new(|| tokio::sync::Mutex::new(System::new_with_specifics(RefreshKind::new().with_memory())));
-
-async fn reserve_if_available(len: usize) -> Result<Vec<u8>, String> {
-    const AVAILABLE_MEMORY_THRESHOLD: u64 = 500_000_000;
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum FetchUnit {
+    Bytes,
+    Rows,
+}
 
-    let lock = SYSTEM.lock().await;
+impl std::convert::TryFrom<u8> for FetchUnit {
+    type Error = Error;
 
-    if (lock.available_memory() as i128) - (AVAILABLE_MEMORY_THRESHOLD as i128) - (len as i128) < 0
-    {
-        return Err(String::from("not enough memory"));
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FetchUnit::Bytes),
+            1 => Ok(FetchUnit::Rows),
+            _ => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid fetch unit code: {}", value),
+            )),
+        }
     }
+}
 
-    Ok(vec![0u8; len])
+impl std::convert::From<FetchUnit> for String {
+    fn from(unit: FetchUnit) -> Self {
+        match unit {
+            FetchUnit::Bytes => String::from("bytes"),
+            FetchUnit::Rows => String::from("rows"),
+        }
+    }
 }
 
 pub struct MatrixData {
-    matrix_type_size: usize,
-    matrix_dimensions: usize,
+    matrix_type: MatrixType,
+    matrix_rows: usize,
+    matrix_columns: usize,
     matrix_vec: Vec<u8>,
+    operation: Operation,
+    // applied to the result of the operation
+    conversion: Option<Conversion>,
+    received: ReceivedRanges,
+    // the last time a part of the matrix was uploaded
+    touched: Instant,
+    cancelled: Arc<AtomicBool>,
+    // what the scheduler needs to know to start the job in its turn
+    priority: u8,
+    client: ClientId,
+    // held from the moment the whole matrix is received until it's transposed
+    queue_slot: Option<QueueSlot>,
+}
+
+// what a job holds from its reservation until its result is released, given
+// back when it's dropped
+pub struct Holdings {
+    memory: MemoryReservation,
+    quota: JobQuota,
 }
 
+// every state that holds memory holds its share of the ledgers too
 pub enum Task {
     NoData,
-    Reserved(MatrixData),
-    Ready(MatrixData),
-    Running,
-    Completed(Vec<u8>),
+    Reserved(MatrixData, Holdings),
+    // the flag is shared with the thread pool to stop the job early. The
+    // holdings move to the computation once it starts, so that they're given
+    // back only when the thread pool is done with the buffers
+    Running(Arc<AtomicBool>, Option<Holdings>),
+    Completed {
+        matrix_type_size: usize,
+        matrix_rows: u32,
+        matrix_columns: u32,
+        matrix_bytes: Bytes,
+        // the last time the result was downloaded
+        touched: Instant,
+        // only held until the result is released
+        holdings: Holdings,
+    },
+    Cancelled {
+        // when the job was cancelled, it's forgotten if nobody polls it
+        touched: Instant,
+    },
 }
 
-impl Task {
-    async fn reserve(
-        self_arc: &Arc<tokio::sync::Mutex<Self>>,
+pub struct Job {
+    task: tokio::sync::Mutex<Task>,
+    // woken up when the task is completed, cancelled or expired
+    finished: Notify,
+}
+
+/// An uploaded job on its way to the thread pool, with the matrix it works on.
+pub type QueuedJob = (MatrixData, Arc<Job>);
+
+impl Job {
+    fn new() -> Job {
+        Job {
+            task: tokio::sync::Mutex::new(Task::NoData),
+            finished: Notify::new(),
+        }
+    }
+}
+
+impl MatrixData {
+    // the length has to be checked against the job limits beforehand
+    #[allow(clippy::too_many_arguments)]
+    fn new(
         matrix_type: MatrixType,
-        matrix_dimension: u32,
-    ) -> Result<(), String> {
-        let mut lock = self_arc.lock().await;
-        match *lock {
-            Task::NoData => (),
-            _ => panic!("calling reserve on a task other than Task::NoData"),
+        matrix_rows: u32,
+        matrix_columns: u32,
+        matrix_len: usize,
+        operation: Operation,
+        conversion: Option<Conversion>,
+        priority: u8,
+        client: ClientId,
+    ) -> MatrixData {
+        MatrixData {
+            matrix_type,
+            matrix_rows: matrix_rows as usize,
+            matrix_columns: matrix_columns as usize,
+            matrix_vec: vec![0u8; matrix_len],
+            operation,
+            conversion,
+            received: ReceivedRanges::default(),
+            touched: Instant::now(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            priority,
+            client,
+            queue_slot: None,
+        }
+    }
+
+    // the type of the elements the job ends up with
+    fn result_type(&self) -> MatrixType {
+        match self.conversion {
+            Some(conversion) => conversion.matrix_type,
+            None => self.operation.output_type(self.matrix_type),
+        }
+    }
+
+    // the part of the matrix a chunk covers
+    fn chunk_range(&self, offset: usize, len: usize) -> Result<Range<usize>, Error> {
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= self.matrix_vec.len())
+            .ok_or(Error::new(
+                ErrorCode::InvalidArgument,
+                "the chunk doesn't fit into the matrix",
+            ))?;
+        Ok(offset..end)
+    }
+
+    // copies one chunk of the matrix, returns the number of bytes received so far
+    fn store(&mut self, range: Range<usize>, chunk: &[u8]) -> usize {
+        self.matrix_vec[range.clone()].copy_from_slice(chunk);
+        self.received.insert(range);
+        self.touched = Instant::now();
+        self.received.received()
+    }
+}
+
+// Every request that touches a task goes through one of these transitions while
+// holding the task's lock. A request the task can't serve in its current state
+// gets an error and leaves the task as it was.
+impl Task {
+    fn invalid_state(&self) -> Error {
+        let state = match self {
+            Task::NoData => return unknown_id(),
+            Task::Reserved(..) => "still waiting for its matrix",
+            Task::Running(..) => "running",
+            Task::Completed { .. } => "already completed",
+            Task::Cancelled { .. } => "cancelled",
         };
+        Error::new(ErrorCode::InvalidState, format!("the job is {state}"))
+    }
 
-        let matrix_type_size = matrix_type.get_type_size() as usize;
-        let matrix_dimension = matrix_dimension as usize;
-        let matrix_vec =
-            reserve_if_available(matrix_type_size * matrix_dimension * matrix_dimension).await?;
+    fn reserve(&mut self, data: MatrixData, holdings: Holdings) -> Result<(), Error> {
+        match self {
+            Task::NoData => {
+                *self = Task::Reserved(data, holdings);
+                Ok(())
+            }
+            _ => Err(self.invalid_state()),
+        }
+    }
 
-        let data = MatrixData {
-            matrix_type_size,
-            matrix_dimensions: matrix_dimension,
-            matrix_vec,
+    // returns the size of the matrix if it can still be uploaded
+    fn upload_len(&self) -> Result<usize, Error> {
+        match self {
+            Task::Reserved(data, _) => Ok(data.matrix_vec.len()),
+            _ => Err(self.invalid_state()),
+        }
+    }
+
+    // stores one chunk of the matrix and returns the number of bytes received so
+    // far. Once the whole matrix has been received, the job goes to the thread
+    // pool. The last chunk is refused if the queue is full or the client can't
+    // run one more job
+    fn upload(
+        &mut self,
+        offset: usize,
+        chunk: &[u8],
+        queue: &Arc<JobQueue>,
+        tx: &Sender<QueuedJob>,
+        job: &Arc<Job>,
+    ) -> Result<usize, Error> {
+        let (mut data, mut holdings) = match std::mem::replace(self, Task::NoData) {
+            Task::Reserved(data, holdings) => (data, holdings),
+            task => {
+                let error = task.invalid_state();
+                *self = task;
+                return Err(error);
+            }
         };
 
-        *lock = Task::Reserved(data);
-        Ok(())
+        let mut permit = None;
+        let stored = data.chunk_range(offset, chunk.len()).and_then(|range| {
+            if data.received.received_with(&range) == data.matrix_vec.len() {
+                let queue_slot = queue.enter()?;
+                // the channel is as long as the queue, so a place in the queue
+                // always comes with room in the channel while the server runs
+                let reserved = tx.clone().try_reserve_owned().map_err(|_| {
+                    Error::new(ErrorCode::ServerBusy, "the thread pool takes no more jobs")
+                })?;
+                holdings.quota.start()?;
+                data.queue_slot = Some(queue_slot);
+                permit = Some(reserved);
+            }
+            Ok(data.store(range, chunk))
+        });
+        // the chunks are written to the part of the reservation the matrix takes
+        if let Ok(received) = stored {
+            holdings.memory.set_resident(received as u64);
+        }
+        match (stored, permit) {
+            (Ok(received), Some(permit)) => {
+                *self = Task::Running(Arc::clone(&data.cancelled), Some(holdings));
+                permit.send((data, Arc::clone(job)));
+                Ok(received)
+            }
+            (result, _) => {
+                *self = Task::Reserved(data, holdings);
+                result
+            }
+        }
     }
 
-    async fn fill(self_arc: &Arc<tokio::sync::Mutex<Self>>, stream: &mut TcpStream) -> Result<(), String> {
-        let mut lock = self_arc.lock().await;
-        let task_reserved = std::mem::replace(&mut *lock, Task::NoData);
+    // returns the offset up to which the matrix has been received without gaps
+    fn resume(&self) -> Result<u64, Error> {
+        match self {
+            Task::Reserved(data, _) => Ok(data.received.committed() as u64),
+            _ => Err(self.invalid_state()),
+        }
+    }
 
-        let task_ready = match task_reserved {
-            Task::Reserved(mut data) => {
-                let n = stream
-                    .read_exact(&mut data.matrix_vec)
-                    .await
-                    .map_err(|e| e.to_string())?;
-                if n == 0 {
-                    Err(String::from("the client disconnected"))?
-                }
+    // hands the holdings over to the computation, unless the job was cancelled
+    // while it was waiting for its turn
+    fn start(&mut self) -> Option<Holdings> {
+        match self {
+            Task::Running(_, holdings) => holdings.take(),
+            _ => None,
+        }
+    }
 
-                Task::Ready(data)
+    // the result is dropped if the job was cancelled or expired in the meantime,
+    // and the holdings of the computation with it
+    fn complete(
+        &mut self,
+        matrix_type_size: usize,
+        matrix_rows: u32,
+        matrix_columns: u32,
+        matrix_vec: Vec<u8>,
+        mut holdings: Holdings,
+    ) {
+        *self = match std::mem::replace(self, Task::NoData) {
+            Task::Running(..) => {
+                // the original matrix is gone, only the result is held now
+                holdings.memory.shrink_to(matrix_vec.len() as u64);
+                holdings.memory.set_resident(matrix_vec.len() as u64);
+                holdings.quota.finish();
+                Task::Completed {
+                    matrix_type_size,
+                    matrix_rows,
+                    matrix_columns,
+                    matrix_bytes: Bytes::from(matrix_vec),
+                    touched: Instant::now(),
+                    holdings,
+                }
             }
-            _ => panic!("calling fill on a task other than Task::Reserved"),
+            task => task,
         };
+    }
 
-        *lock = task_ready;
-        Ok(())
+    fn status(&mut self, queue_depth: u32) -> Status {
+        match self {
+            Task::NoData => Status::NoData,
+            Task::Reserved(data, _) => Status::Reserved {
+                received_bytes: data.received.received() as u64,
+                total_bytes: data.matrix_vec.len() as u64,
+            },
+            Task::Running(..) => Status::Running { queue_depth },
+            Task::Completed {
+                matrix_rows,
+                matrix_columns,
+                matrix_bytes,
+                touched,
+                ..
+            } => {
+                *touched = Instant::now();
+                Status::Completed {
+                    matrix_rows: *matrix_rows,
+                    matrix_columns: *matrix_columns,
+                    matrix_bytes: matrix_bytes.clone(),
+                }
+            }
+            Task::Cancelled { .. } => Status::Cancelled,
+        }
     }
 
-    async fn run(
-        arc_self: Arc<tokio::sync::Mutex<Self>>,
-        thread_pool_tx: &UnboundedSender<(MatrixData, Arc<tokio::sync::Mutex<Self>>)>,
-    ) {
-        let task_ready = {
-            let mut lock = arc_self.lock().await;
-            std::mem::replace(&mut *lock, Task::Running)
+    // the range is cut short to fit into `max_len` bytes, in whole units
+    fn fetch(
+        &mut self,
+        unit: FetchUnit,
+        start: u64,
+        count: u64,
+        max_len: u64,
+    ) -> Result<Bytes, Error> {
+        match self {
+            Task::Completed {
+                matrix_type_size,
+                matrix_columns,
+                matrix_bytes,
+                touched,
+                ..
+            } => {
+                let unit_len = match unit {
+                    FetchUnit::Bytes => 1,
+                    FetchUnit::Rows => *matrix_type_size as u64 * *matrix_columns as u64,
+                };
+                let len = matrix_bytes.len() as u64;
+                let begin = start.saturating_mul(unit_len);
+                if begin > len {
+                    Err(Error::new(
+                        ErrorCode::InvalidArgument,
+                        "the range starts past the end of the matrix",
+                    ))?
+                }
+                let max_count = max_len / unit_len;
+                if max_count == 0 {
+                    Err(Error::new(
+                        ErrorCode::TooLarge,
+                        format!("a row is {unit_len} bytes long, a response can hold at most {max_len} bytes"),
+                    ))?
+                }
+                let end = begin
+                    .saturating_add(count.min(max_count).saturating_mul(unit_len))
+                    .min(len);
+
+                *touched = Instant::now();
+                Ok(matrix_bytes.slice(begin as usize..end as usize))
+            }
+            _ => Err(self.invalid_state()),
+        }
+    }
+
+    fn cancel(&mut self) -> Result<(), Error> {
+        match self {
+            Task::NoData => return Err(unknown_id()),
+            Task::Running(cancelled, _) => cancelled.store(true, Ordering::Relaxed),
+            _ => (),
         };
-        match task_ready {
-            Task::Ready(data) => thread_pool_tx
-                .send((data, arc_self))
-                .unwrap_or_else(|_| panic!("couldn't send the data to the thread pool manager")),
-            _ => panic!("calling run on a task other than Task::Ready"),
+        *self = Task::Cancelled {
+            touched: Instant::now(),
         };
+        Ok(())
+    }
+
+    // the job is left with nothing to hold, it's forgotten by its manager
+    fn release(&mut self) -> Result<(), Error> {
+        match self {
+            Task::Completed { .. } => {
+                *self = Task::NoData;
+                Ok(())
+            }
+            _ => Err(self.invalid_state()),
+        }
+    }
+
+    // what the job holds, unless its computation holds it right now
+    fn holdings_mut(&mut self) -> Option<&mut Holdings> {
+        match self {
+            Task::Reserved(_, holdings) | Task::Completed { holdings, .. } => Some(holdings),
+            Task::Running(_, holdings) => holdings.as_mut(),
+            Task::NoData | Task::Cancelled { .. } => None,
+        }
+    }
+
+    // whether there's nothing more to wait for
+    fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Task::Completed { .. } | Task::Cancelled { .. } | Task::NoData
+        )
+    }
+
+    fn is_expired(&self, upload_grace_period: Duration, result_ttl: Duration) -> bool {
+        match self {
+            Task::Reserved(data, _) => data.touched.elapsed() > upload_grace_period,
+            Task::Completed { touched, .. } | Task::Cancelled { touched } => {
+                touched.elapsed() > result_ttl
+            }
+            _ => false,
+        }
     }
 }
 
-struct PointerWrapper(*mut u8);
-unsafe impl Sync for PointerWrapper {}
-
-// pub async fn transpose(tp: &rayon::ThreadPool, data: MatrixData) -> Vec<u8> {
-//     let type_size = data.matrix_type_size;
-//     let dimension = data.matrix_dimensions;
-//     let mut matrix_vec = data.matrix_vec;
-
-//     let (tx, rx) = tokio::sync::oneshot::channel();
-//     let closure = move || {
-//         let vec_ptr = &PointerWrapper(matrix_vec.as_mut_ptr());
-//         matrix_vec
-//             .par_chunks_exact_mut(type_size)
-//             .enumerate()
-//             .map(|(i, ch)| (i / dimension, i % dimension, ch))
-//             .filter(|(i, j, _)| i < j)
-//             .map(|(i, j, lower_chunk)| {
-//                 (lower_chunk, unsafe {
-//                     std::slice::from_raw_parts_mut(
-//                         vec_ptr.0.add((j * dimension + i) * type_size),
-//                         type_size,
-//                     )
-//                 })
-//             })
-//             .for_each(|(l, u)| l.swap_with_slice(u));
-
-//         tx.send(matrix_vec).unwrap();
-//     };
-
-//     tp.install(closure);
-
-//     rx.await.unwrap()
-// }
-
-// actually translates to something like this:
-enum TransposeState<'a> {
+// runs the computation on the thread pool and resolves to its result once the
+// thread pool sends it back
+enum ComputeState<'a> {
     Initialized {
         tp: &'a rayon::ThreadPool,
         data: MatrixData,
@@ -156,15 +454,15 @@ enum TransposeState<'a> {
     Terminated,
 }
 
-struct Transpose<'a> {
-    state: Arc<Mutex<TransposeState<'a>>>,
+struct Compute<'a> {
+    state: Arc<Mutex<ComputeState<'a>>>,
 
     // this would be needed if this task's work were long and didn't solely depend
-    // on awaiting the oneshot receiver with the transposed matrix from the thread pool
+    // on awaiting the oneshot receiver with the result from the thread pool
     // waker: Option<Arc<Mutex<Waker>>>,
 }
 
-impl<'a> Future for Transpose<'a> {
+impl<'a> Future for Compute<'a> {
     type Output = Vec<u8>;
 
     fn poll(
@@ -173,51 +471,75 @@ impl<'a> Future for Transpose<'a> {
     ) -> std::task::Poll<Self::Output> {
         let mut state_lock = self.state.lock().unwrap();
         loop {
-            let previous_state = std::mem::replace(&mut *state_lock, TransposeState::Terminated);
+            let previous_state = std::mem::replace(&mut *state_lock, ComputeState::Terminated);
             match previous_state {
-                TransposeState::Initialized { tp, data } => {
-                    let type_size = data.matrix_type_size;
-                    let dimension = data.matrix_dimensions;
+                ComputeState::Initialized { tp, data } => {
+                    let operation = data.operation;
+                    let conversion = data.conversion;
+                    let matrix_type = data.matrix_type;
+                    let rows = data.matrix_rows;
+                    let columns = data.matrix_columns;
                     let mut matrix_vec = data.matrix_vec;
+                    let cancelled = data.cancelled;
 
                     let (tx, rx) = tokio::sync::oneshot::channel();
                     let closure = move || {
-                        let vec_ptr = &PointerWrapper(matrix_vec.as_mut_ptr());
-                        matrix_vec
-                            .par_chunks_exact_mut(type_size)
-                            .enumerate()
-                            .map(|(i, ch)| (i / dimension, i % dimension, ch))
-                            .filter(|(i, j, _)| i < j)
-                            .map(|(i, j, lower_chunk)| {
-                                (lower_chunk, unsafe {
-                                    std::slice::from_raw_parts_mut(
-                                        vec_ptr.0.add((j * dimension + i) * type_size),
-                                        type_size,
-                                    )
-                                })
-                            })
-                            .for_each(|(l, u)| l.swap_with_slice(u));
-
-                        tx.send(matrix_vec).unwrap();
+                        let result_vec = match operation {
+                            Operation::Multiply(product) => matmul::multiply(
+                                matrix_type,
+                                &matrix_vec,
+                                rows,
+                                columns,
+                                product,
+                                &cancelled,
+                            ),
+                            Operation::Reduce(reduction) => reduce::reduce(
+                                reduction,
+                                matrix_type,
+                                &matrix_vec,
+                                rows,
+                                columns,
+                                &cancelled,
+                            ),
+                            // a transposition in place takes the input with it
+                            operation => kernel::apply(
+                                operation,
+                                matrix_type,
+                                std::mem::take(&mut matrix_vec),
+                                rows,
+                                columns,
+                                &cancelled,
+                            ),
+                        };
+                        // the input isn't needed anymore and makes room for the conversion
+                        drop(matrix_vec);
+                        let result_vec = match conversion {
+                            Some(conversion) => {
+                                let result_type = operation.output_type(matrix_type);
+                                convert::convert(result_type, conversion, &result_vec, &cancelled)
+                            }
+                            None => result_vec,
+                        };
+                        tx.send(result_vec).unwrap();
                     };
 
-                    tp.install(closure);
+                    tp.spawn(closure);
 
-                    *state_lock = TransposeState::AwaitingResult(rx);
+                    *state_lock = ComputeState::AwaitingResult(rx);
                 }
-                TransposeState::AwaitingResult(mut rx) => {
+                ComputeState::AwaitingResult(mut rx) => {
                     match Pin::new(&mut rx).poll(cx) {
                         Poll::Ready(result) => {
-                            *state_lock = TransposeState::Terminated;
+                            *state_lock = ComputeState::Terminated;
                             return Poll::Ready(result.unwrap());
                         },
                         Poll::Pending => {
-                            *state_lock = TransposeState::AwaitingResult(rx);
+                            *state_lock = ComputeState::AwaitingResult(rx);
                             return Poll::Pending;
                         },
                     }
                 }
-                TransposeState::Terminated => {
+                ComputeState::Terminated => {
                     panic!("polled after termination");
                 }
             }
@@ -225,121 +547,447 @@ impl<'a> Future for Transpose<'a> {
     }
 }
 
-pub fn transpose(tp: &rayon::ThreadPool, data: MatrixData) -> impl Future<Output = Vec<u8>> + '_ {
-    Transpose{ state: Arc::new(Mutex::new(TransposeState::Initialized { tp, data })) }
+pub fn compute(tp: &rayon::ThreadPool, data: MatrixData) -> impl Future<Output = Vec<u8>> + '_ {
+    Compute{ state: Arc::new(Mutex::new(ComputeState::Initialized { tp, data })) }
 }
 
+async fn complete(
+    job: Arc<Job>,
+    matrix_type_size: usize,
+    matrix_rows: u32,
+    matrix_columns: u32,
+    matrix_vec: Vec<u8>,
+    holdings: Holdings,
+) {
+    job.task.lock().await.complete(
+        matrix_type_size,
+        matrix_rows,
+        matrix_columns,
+        matrix_vec,
+        holdings,
+    );
+    job.finished.notify_waiters();
+}
+
+/// Keeps up to `max_concurrent_jobs` jobs in flight on the thread pool.
+/// The jobs that come from the channel wait in the scheduler, which picks the
+/// next one to start as soon as one of the running jobs finishes. The channel
+/// is as long as the queue, as it never holds more than the queue lets in.
 pub async fn process_tasks(
     tp: rayon::ThreadPool,
-    mut process_tasks_channel_rx: UnboundedReceiver<(MatrixData, Arc<tokio::sync::Mutex<Task>>)>,
+    mut process_tasks_channel_rx: mpsc::Receiver<QueuedJob>,
+    max_concurrent_jobs: usize,
+    policy: Policy,
+    queue: Arc<JobQueue>,
 ) {
+    let mut scheduler: Scheduler<QueuedJob> = Scheduler::new(policy);
+    let mut in_flight = FuturesUnordered::new();
     loop {
-        let (data, task_arc) = process_tasks_channel_rx.recv().await.unwrap();
-
-        let matrix_vec = transpose(&tp, data).await;
+        while in_flight.len() < max_concurrent_jobs {
+            let Some((mut data, job)) = scheduler.pop() else {
+                break;
+            };
+            data.queue_slot = None;
+            // a job cancelled while it was waiting has nothing left to do
+            if data.cancelled.load(Ordering::Relaxed) {
+                continue;
+            }
+            let tp = &tp;
+            let queue = &queue;
+            in_flight.push(async move {
+                // held until the computation returns, even if the job is
+                // cancelled in the meantime
+                let Some(holdings) = job.task.lock().await.start() else {
+                    return;
+                };
+                let matrix_type_size = data.result_type().get_type_size() as usize;
+                let (matrix_rows, matrix_columns) = data
+                    .operation
+                    .output_shape(data.matrix_rows as u32, data.matrix_columns as u32);
+                let started = Instant::now();
+                let matrix_vec = compute(tp, data).await;
+                queue.record(started.elapsed());
+                complete(
+                    job,
+                    matrix_type_size,
+                    matrix_rows,
+                    matrix_columns,
+                    matrix_vec,
+                    holdings,
+                )
+                .await;
+            });
+        }
 
-        let task = Task::Completed(matrix_vec);
-        let mut lock = task_arc.lock().await;
-        match *lock {
-            Task::Running => *lock = task,
-            _ => panic!(
-                "trying to complete the task, but it's current state is other than Task::Running"
-            ),
-        };
+        tokio::select! {
+            received = process_tasks_channel_rx.recv() => {
+                // the job manager is gone, so no more jobs will come
+                let Some((data, job)) = received else {
+                    break;
+                };
+                let (priority, client) = (data.priority, data.client.clone());
+                let len = data.matrix_vec.len();
+                scheduler.push((data, job), priority, client, len);
+            }
+            Some(()) = in_flight.next() => (),
+        }
     }
 }
 
+/// The server-wide job registry. Jobs are keyed by unguessable tokens, so a
+/// client can reconnect and keep working with the jobs it reserved earlier.
 pub struct JobManager {
-    task_iterator: usize,
-    tasks: HashMap<usize, Arc<tokio::sync::Mutex<Task>>>,
-    process_tasks_channel_tx: UnboundedSender<(MatrixData, Arc<tokio::sync::Mutex<Task>>)>,
+    tasks: Mutex<HashMap<Token, Arc<Job>>>,
+    process_tasks_channel_tx: Sender<QueuedJob>,
+    // how long a reserved matrix is kept without any part of it being uploaded
+    upload_grace_period: Duration,
+    // how long a result is kept without being downloaded
+    result_ttl: Duration,
+    memory: Arc<MemoryLedger>,
+    limits: JobLimits,
+    quotas: Arc<QuotaLedger>,
+    queue: Arc<JobQueue>,
+    // the longest payload of a response, a larger result is fetched in parts
+    max_frame_len: u64,
+}
+
+fn unknown_id() -> Error {
+    Error::new(ErrorCode::UnknownId, "the id is not reserved")
 }
 
 impl JobManager {
+    fn get(&self, id: Token) -> Option<Arc<Job>> {
+        self.tasks.lock().unwrap().get(&id).map(Arc::clone)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn reserve(
-        &mut self,
+        &self,
+        client: &ClientId,
         matrix_type: MatrixType,
-        matrix_dimension: u32,
-    ) -> Result<usize, String> {
-        let task_arc = Arc::new(tokio::sync::Mutex::new(Task::NoData));
-        Task::reserve(&task_arc, matrix_type, matrix_dimension).await?;
-
-        let id = self.task_iterator;
-        self.tasks.insert(id, task_arc);
-        self.task_iterator += 1;
+        matrix_rows: u32,
+        matrix_columns: u32,
+        operation: Operation,
+        conversion: Option<Conversion>,
+        priority: u8,
+    ) -> Result<Token, Error> {
+        let len = self
+            .limits
+            .input_len(matrix_type, matrix_rows, matrix_columns, operation)?;
+        // the result is held to the same limits
+        let (result_rows, result_columns) = operation.output_shape(matrix_rows, matrix_columns);
+        let result_type = operation.output_type(matrix_type);
+        let result_len = self
+            .limits
+            .matrix_len(result_type, result_rows, result_columns)?;
+        // converting to the type the result already has is a no-op
+        let conversion = conversion.filter(|c| c.matrix_type != result_type);
+        let converted_len = match conversion {
+            Some(conversion) => {
+                self.limits
+                    .matrix_len(conversion.matrix_type, result_rows, result_columns)?
+            }
+            None => 0,
+        };
+        let memory_len = match kernel::in_place(operation, matrix_rows, matrix_columns) {
+            // the input becomes the result, which is held until it's converted
+            true => (len as u64).checked_add(converted_len as u64),
+            // the input is dropped before the result is converted, so at most two
+            // of the three buffers are held at once
+            false => (len.max(converted_len) as u64).checked_add(result_len as u64),
+        }
+        .ok_or(Error::new(
+            ErrorCode::TooLarge,
+            format!("{len} and {result_len} bytes can't be held at once"),
+        ))?;
+        let holdings = Holdings {
+            quota: self.quotas.reserve(client, len as u64)?,
+            memory: self.memory.reserve(memory_len)?,
+        };
+        let data = MatrixData::new(
+            matrix_type,
+            matrix_rows,
+            matrix_columns,
+            len,
+            operation,
+            conversion,
+            priority,
+            client.clone(),
+        );
+        let job = Arc::new(Job::new());
+        job.task.lock().await.reserve(data, holdings)?;
+
+        let mut tasks = self.tasks.lock().unwrap();
+        let id = loop {
+            let id = Token::generate();
+            if !tasks.contains_key(&id) {
+                break id;
+            }
+        };
+        tasks.insert(id, job);
         Ok(id)
     }
 
-    pub async fn calc(&mut self, id: usize, stream: &mut TcpStream) -> Result<(), String> {
-        let task_arc = self.tasks.get(&id).ok_or("the id is not reserved")?;
-        Task::fill(task_arc, stream).await?;
-        Task::run(Arc::clone(task_arc), &self.process_tasks_channel_tx).await;
+    /// Uploads the whole matrix at once, so it has to be exactly as long as
+    /// the reservation.
+    pub async fn calc(&self, id: Token, matrix: &[u8]) -> Result<(), Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        let len = job.task.lock().await.upload_len()?;
+        if matrix.len() != len {
+            Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!(
+                    "the matrix is {} bytes long, the reservation is for {len} bytes",
+                    matrix.len()
+                ),
+            ))?
+        }
+
+        self.upload_chunk(&job, 0, matrix).await?;
         Ok(())
     }
 
-    pub async fn poll(&mut self, id: usize) -> Status {
-        let task = self.tasks.get(&id);
-        match task {
-            Some(task_arc) => {
-                let task = task_arc.lock().await;
-                match *task {
-                    Task::Reserved(_) => return Status::Reserved,
-                    Task::Ready(_) => return Status::Running,
-                    Task::Running => return Status::Running,
-                    Task::NoData => {
-                        panic!("found a Task::NoData in the hash map, which is not allowed")
-                    }
-                    Task::Completed(_) => (),
-                };
+    /// Stores a chunk of the matrix and starts the job as soon as the last
+    /// missing chunk arrives. Returns the number of bytes received so far.
+    pub async fn upload(&self, id: Token, offset: u64, chunk: &[u8]) -> Result<u64, Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        let offset = usize::try_from(offset)
+            .map_err(|e| Error::new(ErrorCode::InvalidArgument, e.to_string()))?;
+        let received = self.upload_chunk(&job, offset, chunk).await?;
+        Ok(received as u64)
+    }
+
+    async fn upload_chunk(
+        &self,
+        job: &Arc<Job>,
+        offset: usize,
+        chunk: &[u8],
+    ) -> Result<usize, Error> {
+        job.task.lock().await.upload(
+            offset,
+            chunk,
+            &self.queue,
+            &self.process_tasks_channel_tx,
+            job,
+        )
+    }
+
+    /// Counts the job against the quota of the client that works on it now,
+    /// which may be a new connection of the client that reserved it.
+    pub async fn reattach(&self, id: Token, client: &ClientId) {
+        if let Some(job) = self.get(id) {
+            if let Some(holdings) = job.task.lock().await.holdings_mut() {
+                holdings.quota.move_to(client);
             }
+        }
+    }
+
+    /// Returns the offset up to which the matrix has been received without gaps,
+    /// which is where a client should continue an interrupted upload.
+    pub async fn resume(&self, id: Token) -> Result<u64, Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        let result = job.task.lock().await.resume();
+        result
+    }
+
+    /// Forgets the reserved matrices nobody has uploaded anything to for longer
+    /// than the grace period, and the results nobody has downloaded and the
+    /// cancellations nobody has polled for longer than their time to live.
+    pub async fn expire_tasks(&self) {
+        let tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, job)| (*id, Arc::clone(job)))
+            .collect();
+
+        for (id, job) in tasks {
+            // a locked task is being worked with right now
+            let mut task = match job.task.try_lock() {
+                Ok(task) => task,
+                Err(_) => continue,
+            };
+            if task.is_expired(self.upload_grace_period, self.result_ttl) {
+                eprintln!("The job {id} expired");
+                *task = Task::NoData;
+                drop(task);
+                self.tasks.lock().unwrap().remove(&id);
+                job.finished.notify_waiters();
+            }
+        }
+    }
+
+    fn queue_depth(&self) -> u32 {
+        self.queue.depth().try_into().unwrap_or(u32::MAX)
+    }
+
+    // what the client holds right now
+    pub fn usage(&self, client: &ClientId) -> Usage {
+        self.quotas.usage(client)
+    }
+
+    pub async fn poll(&self, id: Token) -> Status {
+        let job = match self.get(id) {
+            Some(job) => job,
             None => return Status::NoData,
         };
 
-        let task_arc = self.tasks.remove(&id).unwrap();
-        let task_lock = Arc::try_unwrap(task_arc).unwrap_or_else(|_| {
-            panic!("arc reference count is more then one, but the task is marked as completed")
-        });
-        let task = task_lock.into_inner();
-        match task {
-            Task::Completed(matrix_bytes) => Status::Completed { matrix_bytes },
-            _ => unreachable!(),
+        let mut status = job.task.lock().await.status(self.queue_depth());
+        // a result that doesn't fit into the response is downloaded with fetch requests
+        if let Status::Completed { matrix_bytes, .. } = &mut status {
+            let max_len = self
+                .max_frame_len
+                .saturating_sub(status::COMPLETED_HEADER_LEN);
+            if matrix_bytes.len() as u64 > max_len {
+                *matrix_bytes = Bytes::new();
+            }
         }
+        // a cancelled job is forgotten once the client has learned about it
+        if let Status::Cancelled = status {
+            self.tasks.lock().unwrap().remove(&id);
+        }
+        status
+    }
+
+    /// Returns a part of a completed result, `count` bytes or rows starting
+    /// from `start`. The range is cut short at the end of the matrix and to fit
+    /// into a response.
+    pub async fn fetch(
+        &self,
+        id: Token,
+        unit: FetchUnit,
+        start: u64,
+        count: u64,
+    ) -> Result<Bytes, Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        let result = job
+            .task
+            .lock()
+            .await
+            .fetch(unit, start, count, self.max_frame_len);
+        result
+    }
+
+    /// Frees the job's memory right away. A running job is stopped
+    /// cooperatively, and the job is remembered as cancelled until it's polled.
+    /// Cancelling a completed job releases its result.
+    pub async fn cancel(&self, id: Token) -> Result<(), Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        job.task.lock().await.cancel()?;
+        job.finished.notify_waiters();
+        Ok(())
+    }
+
+    /// Frees the result of a completed job and forgets the job, once the client
+    /// has downloaded everything it needs.
+    pub async fn release(&self, id: Token) -> Result<(), Error> {
+        let job = self.get(id).ok_or_else(unknown_id)?;
+        job.task.lock().await.release()?;
+        self.tasks.lock().unwrap().remove(&id);
+        Ok(())
+    }
+
+    /// Waits until the job is completed, cancelled or expired, or until the
+    /// timeout runs out, and returns the job's status at that moment.
+    pub async fn wait(&self, id: Token, timeout: Option<Duration>) -> Status {
+        let job = match self.get(id) {
+            Some(job) => job,
+            None => return Status::NoData,
+        };
+
+        let finished = async {
+            loop {
+                // created before checking the task so that a notification
+                // sent in between isn't lost
+                let notified = job.finished.notified();
+                if job.task.lock().await.is_finished() {
+                    break;
+                }
+                notified.await;
+            }
+        };
+        match timeout {
+            Some(timeout) => {
+                let _ = tokio::time::timeout(timeout, finished).await;
+            }
+            None => finished.await,
+        };
+
+        self.poll(id).await
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn new_manager(
-    tx: tokio::sync::mpsc::UnboundedSender<(MatrixData, Arc<tokio::sync::Mutex<Task>>)>,
+    tx: Sender<QueuedJob>,
+    upload_grace_period: Duration,
+    result_ttl: Duration,
+    memory: Arc<MemoryLedger>,
+    limits: JobLimits,
+    quotas: Arc<QuotaLedger>,
+    queue: Arc<JobQueue>,
+    max_frame_len: u64,
 ) -> JobManager {
     JobManager {
-        task_iterator: 1,
-        tasks: HashMap::<usize, Arc<tokio::sync::Mutex<Task>>>::new(),
+        tasks: Mutex::new(HashMap::new()),
         process_tasks_channel_tx: tx,
+        upload_grace_period,
+        result_ttl,
+        memory,
+        limits,
+        quotas,
+        queue,
+        max_frame_len,
     }
 }
 
-trait FormatAsMatrix {
-    fn format_as_matrix(&self, type_size: usize, dim: usize) -> String;
-}
-
-impl FormatAsMatrix for Vec<u8> {
-    fn format_as_matrix(&self, type_size: usize, dim: usize) -> String {
-        const MAX_CORNER_DIM: usize = 5;
-
-        self.chunks_exact(type_size)
-            .map(|num| format!("{:>16}", hex::encode(num)))
-            .enumerate()
-            .filter(|(i, _)| (i / dim <= MAX_CORNER_DIM) && (i % dim <= MAX_CORNER_DIM))
-            .group_by(|(i, _)| i / MAX_CORNER_DIM)
-            .into_iter()
-            .map(|(_, g)| g.map(|(_, num_str)| num_str).join(" "))
-            .join("\n")
+pub async fn expire_tasks(job_manager: Arc<JobManager>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        job_manager.expire_tasks().await;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        memory::{MemoryCap, AVAILABLE_MEMORY_THRESHOLD},
+        operation::{Arithmetic, Product, Reduction},
+        quota::Quota,
+        request::Request,
+        response::Response,
+    };
+    use itertools::Itertools;
+    use rayon::prelude::*;
     use std::io::Write;
+    use std::sync::OnceLock;
+
+    const UNLIMITED: JobLimits = JobLimits {
+        max_dimension: u32::MAX,
+        max_job_bytes: u64::MAX,
+    };
+
+    trait FormatAsMatrix {
+        fn format_as_matrix(&self, type_size: usize, dim: usize) -> String;
+    }
+
+    impl FormatAsMatrix for Vec<u8> {
+        fn format_as_matrix(&self, type_size: usize, dim: usize) -> String {
+            const MAX_CORNER_DIM: usize = 5;
+
+            self.chunks_exact(type_size)
+                .map(|num| format!("{:>16}", hex::encode(num)))
+                .enumerate()
+                .filter(|(i, _)| (i / dim <= MAX_CORNER_DIM) && (i % dim <= MAX_CORNER_DIM))
+                .group_by(|(i, _)| i / MAX_CORNER_DIM)
+                .into_iter()
+                .map(|(_, g)| g.map(|(_, num_str)| num_str).join(" "))
+                .join("\n")
+        }
+    }
 
     struct TestCase {
         num_threads: usize,
@@ -382,6 +1030,16 @@ mod tests {
         _transposition(false);
     }
 
+    // transposition only cares about the size of the elements
+    fn unsigned(type_size: usize) -> MatrixType {
+        match type_size {
+            1 => MatrixType::U8,
+            2 => MatrixType::U16,
+            4 => MatrixType::U32,
+            _ => MatrixType::U64,
+        }
+    }
+
     fn _transposition(print_matrices: bool) {
         let mut test_cases = test_cases();
 
@@ -409,13 +1067,22 @@ mod tests {
                     .collect();
 
                 let matrix_data = MatrixData {
-                    matrix_type_size: test_case.matrix_type_size,
-                    matrix_dimensions: test_case.matrix_dimensions,
+                    matrix_type: unsigned(test_case.matrix_type_size),
+                    matrix_rows: test_case.matrix_dimensions,
+                    matrix_columns: test_case.matrix_dimensions,
                     matrix_vec: orig_vec.clone(),
+                    operation: Operation::Transpose,
+                    conversion: None,
+                    received: ReceivedRanges::default(),
+                    touched: Instant::now(),
+                    cancelled: Arc::new(AtomicBool::new(false)),
+                    priority: 0,
+                    client: ClientId::new(None),
+                    queue_slot: None,
                 };
 
                 let begin_time = std::time::Instant::now();
-                let transposed_vec = transpose(&tp, matrix_data).await;
+                let transposed_vec = compute(&tp, matrix_data).await;
                 let end_time = std::time::Instant::now();
                 test_case.duration = end_time - begin_time;
 
@@ -468,4 +1135,1025 @@ mod tests {
                 });
             });
     }
+
+    // the same client all through a test
+    fn client() -> ClientId {
+        static CLIENT: OnceLock<ClientId> = OnceLock::new();
+        CLIENT
+            .get_or_init(|| ClientId::new(Some(String::from("test"))))
+            .clone()
+    }
+
+    // what a test job manager is built with, tests override the parts they look at
+    struct TestOptions {
+        upload_grace_period: Duration,
+        result_ttl: Duration,
+        memory_cap: MemoryCap,
+        quota: Quota,
+        max_queued_jobs: usize,
+        max_frame_len: u64,
+    }
+
+    impl Default for TestOptions {
+        fn default() -> Self {
+            TestOptions {
+                upload_grace_period: Duration::from_secs(600),
+                result_ttl: Duration::from_secs(3600),
+                memory_cap: MemoryCap::Percent(100),
+                quota: Quota::UNLIMITED,
+                // the longest channel there can be
+                max_queued_jobs: tokio::sync::Semaphore::MAX_PERMITS,
+                max_frame_len: u64::MAX,
+            }
+        }
+    }
+
+    // a job manager whose submitted jobs wait in the receiver until a test completes them
+    fn test_manager() -> (JobManager, mpsc::Receiver<QueuedJob>) {
+        test_manager_with(TestOptions::default())
+    }
+
+    fn test_manager_with(options: TestOptions) -> (JobManager, mpsc::Receiver<QueuedJob>) {
+        let (tx, rx) = mpsc::channel(options.max_queued_jobs);
+        let job_manager = new_manager(
+            tx,
+            options.upload_grace_period,
+            options.result_ttl,
+            Arc::new(MemoryLedger::new(options.memory_cap)),
+            UNLIMITED,
+            Arc::new(QuotaLedger::new(options.quota)),
+            Arc::new(JobQueue::new(options.max_queued_jobs)),
+            options.max_frame_len,
+        );
+        (job_manager, rx)
+    }
+
+    const STATES: [&str; 6] = [
+        "unknown",
+        "no data",
+        "reserved",
+        "running",
+        "completed",
+        "cancelled",
+    ];
+
+    async fn job_in_state(
+        job_manager: &JobManager,
+        rx: &mut mpsc::Receiver<QueuedJob>,
+        state: &str,
+    ) -> Token {
+        if state == "unknown" {
+            return Token::generate();
+        }
+        if state == "no data" {
+            // an expired job that a request got hold of just before it was removed
+            let id = Token::generate();
+            job_manager
+                .tasks
+                .lock()
+                .unwrap()
+                .insert(id, Arc::new(Job::new()));
+            return id;
+        }
+
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                2,
+                3,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        match state {
+            "reserved" => (),
+            "running" => job_manager.calc(id, &[0; 6]).await.unwrap(),
+            "completed" => {
+                job_manager.calc(id, &[0; 6]).await.unwrap();
+                // the jobs left running by earlier calls are still queued
+                let this_job = job_manager.get(id).unwrap();
+                loop {
+                    let (data, job) = rx.recv().await.unwrap();
+                    if Arc::ptr_eq(&job, &this_job) {
+                        finish(job, 1, 3, 2, data.matrix_vec).await;
+                        break;
+                    }
+                }
+            }
+            "cancelled" => job_manager.cancel(id).await.unwrap(),
+            _ => unreachable!(),
+        }
+        id
+    }
+
+    // starts and completes a job the way process_tasks does, with the given result
+    async fn finish(
+        job: Arc<Job>,
+        matrix_type_size: usize,
+        matrix_rows: u32,
+        matrix_columns: u32,
+        matrix_vec: Vec<u8>,
+    ) {
+        let holdings = job.task.lock().await.start().unwrap();
+        complete(
+            job,
+            matrix_type_size,
+            matrix_rows,
+            matrix_columns,
+            matrix_vec,
+            holdings,
+        )
+        .await;
+    }
+
+    async fn state_of(job_manager: &JobManager, id: Token) -> String {
+        match job_manager.get(id) {
+            Some(job) => String::from(&job.task.lock().await.status(0)),
+            None => String::from("unknown"),
+        }
+    }
+
+    fn outcome(response: &Response) -> String {
+        match response {
+            Response::Error { error } => String::from(error.code),
+            Response::Poll { status } | Response::Wait { status } => {
+                format!("{} {}", String::from(response), String::from(status))
+            }
+            response => String::from(response),
+        }
+    }
+
+    #[tokio::test]
+    async fn every_request_in_every_state() {
+        let requests = |id| {
+            [
+                Request::Calc {
+                    id,
+                    matrix: Bytes::from_static(&[0; 6]),
+                },
+                Request::Poll { id },
+                Request::Cancel { id },
+                Request::Upload {
+                    id,
+                    offset: 0,
+                    chunk: Bytes::from_static(&[0; 6]),
+                },
+                Request::Resume { id },
+                Request::Fetch {
+                    id,
+                    unit: FetchUnit::Bytes,
+                    start: 0,
+                    count: 100,
+                },
+                Request::Wait { id, timeout_ms: 1 },
+                Request::Release { id },
+            ]
+        };
+        // the outcomes of the requests above, one row per state
+        #[rustfmt::skip]
+        let expected = [
+            ["unknown id", "poll no data", "unknown id", "unknown id", "unknown id", "unknown id", "wait no data", "unknown id"],
+            ["unknown id", "poll no data", "unknown id", "unknown id", "unknown id", "unknown id", "wait no data", "unknown id"],
+            ["calc", "poll reserved", "cancel", "upload", "resume", "invalid state", "wait reserved", "invalid state"],
+            ["invalid state", "poll running", "cancel", "invalid state", "invalid state", "invalid state", "wait running", "invalid state"],
+            ["invalid state", "poll completed", "cancel", "invalid state", "invalid state", "fetch", "wait completed", "release"],
+            ["invalid state", "poll cancelled", "cancel", "invalid state", "invalid state", "invalid state", "wait cancelled", "invalid state"],
+        ];
+
+        let (job_manager, mut rx) = test_manager();
+        for (state, expected) in STATES.iter().zip(expected) {
+            for (i, expected) in expected.iter().enumerate() {
+                let id = job_in_state(&job_manager, &mut rx, state).await;
+                let state_before = state_of(&job_manager, id).await;
+                let request = requests(id).into_iter().nth(i).unwrap();
+                let request_name = String::from(&request);
+
+                let response = request.execute(&job_manager, &client()).await;
+                assert_eq!(
+                    outcome(&response),
+                    *expected,
+                    "{request_name} on a {state} job"
+                );
+                if let Response::Error { .. } = response {
+                    assert_eq!(
+                        state_of(&job_manager, id).await,
+                        state_before,
+                        "a failed {request_name} changed a {state} job"
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reserve_and_hello_in_any_state() {
+        let (job_manager, _rx) = test_manager();
+
+        let response = Request::Reserve {
+            matrix_type: MatrixType::U8,
+            matrix_rows: 2,
+            matrix_columns: 3,
+            priority: 0,
+            operation: Operation::Transpose,
+            conversion: None,
+        }
+        .execute(&job_manager, &client())
+        .await;
+        assert_eq!(outcome(&response), "reserve");
+
+        let response = Request::Hello {
+            protocol_version: 1,
+            client_name: None,
+        }
+        .execute(&job_manager, &client())
+        .await;
+        assert_eq!(outcome(&response), "protocol violation");
+    }
+
+    #[tokio::test]
+    async fn jobs_give_their_memory_back() {
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            result_ttl: Duration::ZERO,
+            ..TestOptions::default()
+        });
+        let memory = Arc::clone(&job_manager.memory);
+
+        // the matrix and the buffer it's transposed into
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U16,
+                2,
+                3,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(memory.reserved(), 24);
+        job_manager.calc(id, &[0; 12]).await.unwrap();
+        assert_eq!(memory.reserved(), 24);
+
+        // only the result is kept
+        let (data, job) = rx.recv().await.unwrap();
+        finish(job, 2, 3, 2, data.matrix_vec).await;
+        assert_eq!(memory.reserved(), 12);
+
+        // the result expires right away
+        job_manager.expire_tasks().await;
+        assert_eq!(memory.reserved(), 0);
+
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U16,
+                2,
+                3,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        job_manager.cancel(id).await.unwrap();
+        assert_eq!(memory.reserved(), 0);
+
+        // a square matrix is transposed in its own buffer
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U16,
+                3,
+                3,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(memory.reserved(), 18);
+        job_manager.cancel(id).await.unwrap();
+        assert_eq!(memory.reserved(), 0);
+    }
+
+    #[tokio::test]
+    async fn uploaded_matrices_count_once_after_a_refresh() {
+        let (job_manager, _rx) = test_manager();
+        let memory = Arc::clone(&job_manager.memory);
+        let client = client();
+        let reserve =
+            || job_manager.reserve(&client, MatrixType::U8, 2, 3, Operation::Transpose, None, 0);
+
+        // room for two jobs of 12 bytes each
+        let available_memory = AVAILABLE_MEMORY_THRESHOLD + 24;
+        memory.record(u64::MAX, available_memory);
+        let id = reserve().await.unwrap();
+        job_manager.upload(id, 0, &[0; 6]).await.unwrap();
+
+        // the refresh sees the uploaded matrix as used, which the ledger doesn't
+        // count a second time
+        memory.record(u64::MAX, available_memory - 6);
+        reserve().await.unwrap();
+        let error = reserve().await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::NotEnoughMemory);
+    }
+
+    #[tokio::test]
+    async fn reattached_jobs_move_to_the_new_connections_quota() {
+        let (job_manager, _rx) = test_manager();
+        let old = ClientId::new(Some(String::from("test")));
+        let new = ClientId::new(Some(String::from("test")));
+        let id = job_manager
+            .reserve(&old, MatrixType::U8, 2, 3, Operation::Transpose, None, 0)
+            .await
+            .unwrap();
+
+        Request::Resume { id }.execute(&job_manager, &new).await;
+        assert_eq!(job_manager.usage(&old).jobs, 0);
+        let usage = job_manager.usage(&new);
+        assert_eq!((usage.jobs, usage.bytes), (1, 6));
+    }
+
+    #[tokio::test]
+    async fn cancelled_computations_hold_their_memory_until_they_return() {
+        let (job_manager, mut rx) = test_manager();
+        let memory = Arc::clone(&job_manager.memory);
+
+        // a job that's still waiting for its turn gives its memory back right away
+        let waiting = job_in_state(&job_manager, &mut rx, "running").await;
+        assert_eq!(memory.reserved(), 12);
+        job_manager.cancel(waiting).await.unwrap();
+        assert_eq!(memory.reserved(), 0);
+        let (_, job) = rx.recv().await.unwrap();
+        assert!(job.task.lock().await.start().is_none());
+
+        // the thread pool may still be writing to the buffers of a running one
+        let running = job_in_state(&job_manager, &mut rx, "running").await;
+        let (data, job) = rx.recv().await.unwrap();
+        let holdings = job.task.lock().await.start().unwrap();
+        job_manager.cancel(running).await.unwrap();
+        assert_eq!(memory.reserved(), 12);
+        assert_eq!(job_manager.usage(&client()).running, 1);
+
+        complete(job, 1, 3, 2, data.matrix_vec, holdings).await;
+        assert_eq!(memory.reserved(), 0);
+        assert_eq!(job_manager.usage(&client()).running, 0);
+        assert_eq!(job_manager.poll(running).await, Status::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn results_larger_than_a_frame_are_fetched_in_parts() {
+        // the 3x2 result takes 9 + 6 bytes in a poll response
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            max_frame_len: 14,
+            ..TestOptions::default()
+        });
+        let completed = job_in_state(&job_manager, &mut rx, "completed").await;
+        assert_eq!(
+            job_manager.poll(completed).await,
+            Status::Completed {
+                matrix_rows: 3,
+                matrix_columns: 2,
+                matrix_bytes: Bytes::new(),
+            }
+        );
+
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            max_frame_len: 5,
+            ..TestOptions::default()
+        });
+        let completed = job_in_state(&job_manager, &mut rx, "completed").await;
+        let fetch = |unit, start| job_manager.fetch(completed, unit, start, 100);
+        assert_eq!(fetch(FetchUnit::Bytes, 0).await.unwrap().len(), 5);
+        assert_eq!(fetch(FetchUnit::Bytes, 5).await.unwrap().len(), 1);
+        // only whole rows are sent
+        assert_eq!(fetch(FetchUnit::Rows, 0).await.unwrap().len(), 4);
+        assert_eq!(fetch(FetchUnit::Rows, 2).await.unwrap().len(), 2);
+
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            max_frame_len: 1,
+            ..TestOptions::default()
+        });
+        let completed = job_in_state(&job_manager, &mut rx, "completed").await;
+        let error = job_manager
+            .fetch(completed, FetchUnit::Rows, 0, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooLarge);
+    }
+
+    #[tokio::test]
+    async fn released_results_give_their_memory_back() {
+        let (job_manager, mut rx) = test_manager();
+
+        let completed = job_in_state(&job_manager, &mut rx, "completed").await;
+        assert_eq!(job_manager.memory.reserved(), 6);
+        assert_eq!(job_manager.usage(&client()).jobs, 1);
+
+        job_manager.release(completed).await.unwrap();
+        assert_eq!(job_manager.memory.reserved(), 0);
+        assert_eq!(job_manager.usage(&client()).jobs, 0);
+        assert_eq!(job_manager.poll(completed).await, Status::NoData);
+        let error = job_manager.release(completed).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::UnknownId);
+    }
+
+    #[tokio::test]
+    async fn unpolled_cancellations_expire() {
+        let (job_manager, _rx) = test_manager_with(TestOptions {
+            result_ttl: Duration::ZERO,
+            ..TestOptions::default()
+        });
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                2,
+                3,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        job_manager.cancel(id).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        job_manager.expire_tasks().await;
+        assert!(job_manager.tasks.lock().unwrap().is_empty());
+        assert_eq!(job_manager.poll(id).await, Status::NoData);
+    }
+
+    #[tokio::test]
+    async fn oversized_reservations_are_refused() {
+        let (job_manager, _rx) = test_manager();
+
+        for (matrix_type, rows, columns) in [
+            (MatrixType::U64, u32::MAX, u32::MAX),
+            // fits into a u64, but twice of it doesn't, and a matrix that isn't
+            // square can't be transposed in place
+            (MatrixType::U8, u32::MAX, u32::MAX - 1),
+        ] {
+            let error = job_manager
+                .reserve(
+                    &client(),
+                    matrix_type,
+                    rows,
+                    columns,
+                    Operation::Transpose,
+                    None,
+                    0,
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(error.code, ErrorCode::TooLarge);
+        }
+        assert_eq!(job_manager.memory.reserved(), 0);
+        assert!(job_manager.tasks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn quotas_are_enforced_on_reserve_and_calc() {
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            quota: Quota {
+                max_jobs: 2,
+                max_bytes: 10,
+                max_running: 1,
+            },
+            ..TestOptions::default()
+        });
+        let client = client();
+
+        let error = job_manager
+            .reserve(
+                &client,
+                MatrixType::U8,
+                1,
+                11,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        let first = job_manager
+            .reserve(&client, MatrixType::U8, 1, 5, Operation::Transpose, None, 0)
+            .await
+            .unwrap();
+        let second = job_manager
+            .reserve(&client, MatrixType::U8, 1, 5, Operation::Transpose, None, 0)
+            .await
+            .unwrap();
+        let error = job_manager
+            .reserve(&client, MatrixType::U8, 1, 1, Operation::Transpose, None, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        // other clients aren't affected
+        let other = ClientId::new(None);
+        job_manager
+            .reserve(&other, MatrixType::U8, 1, 5, Operation::Transpose, None, 0)
+            .await
+            .unwrap();
+
+        job_manager.calc(first, &[0; 5]).await.unwrap();
+        let error = job_manager.calc(second, &[0; 5]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        // the chunks before the last one can still be uploaded
+        assert_eq!(job_manager.upload(second, 0, &[0; 4]).await, Ok(4));
+        let error = job_manager.upload(second, 4, &[0; 1]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        assert_eq!(job_manager.resume(second).await, Ok(4));
+        assert_eq!(
+            job_manager.usage(&client),
+            Usage {
+                jobs: 2,
+                max_jobs: 2,
+                bytes: 10,
+                max_bytes: 10,
+                running: 1,
+                max_running: 1,
+            }
+        );
+
+        // the second job may run once the first one is done
+        let (data, job) = rx.recv().await.unwrap();
+        finish(job, 1, 5, 1, data.matrix_vec).await;
+        assert_eq!(job_manager.usage(&client).running, 0);
+        assert_eq!(job_manager.upload(second, 4, &[0; 1]).await, Ok(5));
+
+        // a released result gives the whole share back
+        job_manager.cancel(first).await.unwrap();
+        let usage = job_manager.usage(&client);
+        assert_eq!((usage.jobs, usage.bytes, usage.running), (1, 5, 1));
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_refuses_the_last_chunk() {
+        let (job_manager, mut rx) = test_manager_with(TestOptions {
+            max_queued_jobs: 1,
+            ..TestOptions::default()
+        });
+        let first = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                1,
+                2,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        let second = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                1,
+                2,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+
+        job_manager.calc(first, &[0; 2]).await.unwrap();
+        assert_eq!(
+            job_manager.poll(first).await,
+            Status::Running { queue_depth: 1 }
+        );
+        let error = job_manager.calc(second, &[0; 2]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::ServerBusy);
+        assert!(error.retry_after.is_some());
+        // the refused job doesn't count as running
+        assert_eq!(job_manager.usage(&client()).running, 1);
+
+        // the first job leaves the queue once it's started
+        let (data, _job) = rx.recv().await.unwrap();
+        drop(data);
+        job_manager.calc(second, &[0; 2]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn chunks_are_uploaded_in_any_order() {
+        let (job_manager, mut rx) = test_manager();
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                2,
+                5,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        let matrix: Vec<u8> = (1..=10).collect();
+        let progress = |received_bytes| Status::Reserved {
+            received_bytes,
+            total_bytes: 10,
+        };
+        assert_eq!(job_manager.poll(id).await, progress(0));
+
+        assert_eq!(job_manager.upload(id, 4, &matrix[4..7]).await, Ok(3));
+        assert_eq!(job_manager.poll(id).await, progress(3));
+        // overlapping bytes are only counted once
+        assert_eq!(job_manager.upload(id, 2, &matrix[2..5]).await, Ok(5));
+        assert_eq!(job_manager.poll(id).await, progress(5));
+
+        let error = job_manager.upload(id, 8, &[0; 3]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        let error = job_manager.upload(id, u64::MAX, &[0]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+        assert_eq!(job_manager.poll(id).await, progress(5));
+
+        assert_eq!(job_manager.upload(id, 0, &matrix[0..2]).await, Ok(7));
+        assert_eq!(job_manager.poll(id).await, progress(7));
+        // the last missing chunk starts the job
+        assert_eq!(job_manager.upload(id, 7, &matrix[7..]).await, Ok(10));
+        assert_eq!(
+            job_manager.poll(id).await,
+            Status::Running { queue_depth: 1 }
+        );
+        let (data, _job) = rx.recv().await.unwrap();
+        assert_eq!(data.matrix_vec, matrix);
+
+        let error = job_manager.upload(id, 0, &[0]).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidState);
+    }
+
+    #[tokio::test]
+    async fn partial_uploads_are_kept_and_resumed() {
+        let (job_manager, mut rx) = test_manager();
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                1,
+                8,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        let matrix: Vec<u8> = (1..=8).collect();
+        assert_eq!(job_manager.resume(id).await, Ok(0));
+
+        // only the part without gaps counts
+        job_manager.upload(id, 5, &matrix[5..]).await.unwrap();
+        assert_eq!(job_manager.resume(id).await, Ok(0));
+        job_manager.upload(id, 0, &matrix[..3]).await.unwrap();
+        assert_eq!(job_manager.resume(id).await, Ok(3));
+
+        // within the grace period the upload survives the expiry check
+        job_manager.expire_tasks().await;
+        assert_eq!(job_manager.resume(id).await, Ok(3));
+
+        // the client continues from where the upload was cut off
+        assert_eq!(job_manager.upload(id, 3, &matrix[3..5]).await, Ok(8));
+        let (data, _job) = rx.recv().await.unwrap();
+        assert_eq!(data.matrix_vec, matrix);
+        let error = job_manager.resume(id).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidState);
+    }
+
+    #[tokio::test]
+    async fn partial_uploads_expire_after_the_grace_period() {
+        let (job_manager, _rx) = test_manager_with(TestOptions {
+            upload_grace_period: Duration::ZERO,
+            ..TestOptions::default()
+        });
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                1,
+                8,
+                Operation::Transpose,
+                None,
+                0,
+            )
+            .await
+            .unwrap();
+        job_manager.upload(id, 0, &[0; 4]).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        job_manager.expire_tasks().await;
+        let error = job_manager.resume(id).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::UnknownId);
+        assert_eq!(job_manager.poll(id).await, Status::NoData);
+        assert_eq!(job_manager.memory.reserved(), 0);
+        assert_eq!(job_manager.usage(&client()).jobs, 0);
+    }
+
+    #[tokio::test]
+    async fn wait_returns_when_the_job_finishes() {
+        let (job_manager, mut rx) = test_manager();
+        let running = job_in_state(&job_manager, &mut rx, "running").await;
+        let (data, job) = rx.recv().await.unwrap();
+
+        let begin_time = Instant::now();
+        let (status, ()) = tokio::join!(job_manager.wait(running, None), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            finish(job, 1, 3, 2, data.matrix_vec).await;
+        });
+        assert!(begin_time.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            status,
+            Status::Completed {
+                matrix_rows: 3,
+                matrix_columns: 2,
+                matrix_bytes: Bytes::from_static(&[0; 6]),
+            }
+        );
+
+        // a cancellation ends the wait too, and a zero timeout waits for as
+        // long as it takes
+        let reserved = job_in_state(&job_manager, &mut rx, "reserved").await;
+        let wait = Request::Wait {
+            id: reserved,
+            timeout_ms: 0,
+        };
+        let client = client();
+        let (response, ()) = tokio::join!(wait.execute(&job_manager, &client), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            job_manager.cancel(reserved).await.unwrap();
+        });
+        assert_eq!(
+            response,
+            Response::Wait {
+                status: Status::Cancelled
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_returns_when_the_timeout_runs_out() {
+        let (job_manager, mut rx) = test_manager();
+        let running = job_in_state(&job_manager, &mut rx, "running").await;
+
+        let begin_time = Instant::now();
+        let wait = Request::Wait {
+            id: running,
+            timeout_ms: 50,
+        };
+        let response = wait.execute(&job_manager, &client()).await;
+        assert!(begin_time.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            response,
+            Response::Wait {
+                status: Status::Running { queue_depth: 1 }
+            }
+        );
+
+        // a finished job doesn't wait at all
+        let completed = job_in_state(&job_manager, &mut rx, "completed").await;
+        let begin_time = Instant::now();
+        let status = job_manager
+            .wait(completed, Some(Duration::from_secs(60)))
+            .await;
+        assert!(begin_time.elapsed() < Duration::from_secs(60));
+        assert!(matches!(status, Status::Completed { .. }), "{status:?}");
+    }
+
+    #[tokio::test]
+    async fn runs_at_most_max_concurrent_jobs_at_once() {
+        let (job_manager, rx) = test_manager();
+        let tp = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        // the only thread is kept busy, so the started jobs can't finish
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        tp.spawn(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+        let queue = Arc::clone(&job_manager.queue);
+        tokio::spawn(process_tasks(tp, rx, 2, Policy::Fifo, queue));
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let id = job_manager
+                .reserve(
+                    &client(),
+                    MatrixType::U8,
+                    1,
+                    2,
+                    Operation::Transpose,
+                    None,
+                    0,
+                )
+                .await
+                .unwrap();
+            job_manager.calc(id, &[1, 2]).await.unwrap();
+            ids.push(id);
+        }
+
+        // the started jobs leave the queue, the rest wait for one of them to finish
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while job_manager.queue.depth() > 3 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(job_manager.queue.depth(), 3);
+
+        release_tx.send(()).unwrap();
+        for id in ids {
+            assert_eq!(
+                job_manager.wait(id, None).await,
+                Status::Completed {
+                    matrix_rows: 2,
+                    matrix_columns: 1,
+                    matrix_bytes: Bytes::from_static(&[1, 2]),
+                }
+            );
+        }
+        assert_eq!(job_manager.queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn jobs_run_their_operation() {
+        let (job_manager, rx) = test_manager();
+        let tp = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let queue = Arc::clone(&job_manager.queue);
+        tokio::spawn(process_tasks(tp, rx, 2, Policy::Fifo, queue));
+
+        // 1 2 3
+        // 4 5 6
+        let matrix = [1, 2, 3, 4, 5, 6];
+        for (operation, rows, columns, result) in [
+            (Operation::Rotate90, 3, 2, [4, 1, 5, 2, 6, 3]),
+            (Operation::FlipVertical, 2, 3, [4, 5, 6, 1, 2, 3]),
+            (Operation::Negate, 2, 3, [255, 254, 253, 252, 251, 250]),
+        ] {
+            let id = job_manager
+                .reserve(&client(), MatrixType::U8, 2, 3, operation, None, 0)
+                .await
+                .unwrap();
+            job_manager.calc(id, &matrix).await.unwrap();
+            assert_eq!(
+                job_manager.wait(id, None).await,
+                Status::Completed {
+                    matrix_rows: rows,
+                    matrix_columns: columns,
+                    matrix_bytes: Bytes::copy_from_slice(&result),
+                },
+                "{}",
+                String::from(operation)
+            );
+        }
+
+        // the right operand is uploaded after the left one
+        let operation = Operation::Multiply(Product {
+            columns: 1,
+            arithmetic: Arithmetic::Saturating,
+        });
+        let id = job_manager
+            .reserve(&client(), MatrixType::U8, 2, 3, operation, None, 0)
+            .await
+            .unwrap();
+        job_manager
+            .calc(id, &[1, 2, 3, 4, 5, 6, 100, 1, 1])
+            .await
+            .unwrap();
+        assert_eq!(
+            job_manager.wait(id, None).await,
+            Status::Completed {
+                matrix_rows: 2,
+                matrix_columns: 1,
+                matrix_bytes: Bytes::from_static(&[105, 255]),
+            }
+        );
+
+        // the sums of u8 elements are u64, rows of the result are fetched as such
+        let operation = Operation::Reduce(Reduction::RowSums);
+        let id = job_manager
+            .reserve(&client(), MatrixType::U8, 2, 3, operation, None, 0)
+            .await
+            .unwrap();
+        job_manager.calc(id, &matrix).await.unwrap();
+        job_manager.wait(id, None).await;
+        assert_eq!(
+            job_manager.fetch(id, FetchUnit::Rows, 1, 1).await.unwrap(),
+            Bytes::copy_from_slice(&15u64.to_le_bytes())
+        );
+
+        // a converted result is fetched in rows of its own elements
+        let conversion = Conversion {
+            matrix_type: MatrixType::F32,
+            arithmetic: Arithmetic::Saturating,
+        };
+        let id = job_manager
+            .reserve(
+                &client(),
+                MatrixType::U8,
+                2,
+                3,
+                Operation::Transpose,
+                Some(conversion),
+                0,
+            )
+            .await
+            .unwrap();
+        job_manager.calc(id, &matrix).await.unwrap();
+        job_manager.wait(id, None).await;
+        assert_eq!(
+            job_manager.fetch(id, FetchUnit::Rows, 1, 1).await.unwrap(),
+            [2.0f32, 5.0]
+                .iter()
+                .flat_map(|e| e.to_le_bytes())
+                .collect::<Bytes>()
+        );
+    }
+
+    #[tokio::test]
+    async fn rectangular_matrices_end_to_end() {
+        let (job_manager, rx) = test_manager();
+        let tp = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let queue = Arc::clone(&job_manager.queue);
+        tokio::spawn(process_tasks(tp, rx, 2, Policy::Fifo, queue));
+
+        // wide and tall, with partial tiles on both sides
+        for (matrix_type, rows, columns) in [
+            (MatrixType::U8, 1, 70),
+            (MatrixType::U16, 70, 1),
+            (MatrixType::U32, 37, 53),
+            (MatrixType::U64, 130, 65),
+        ] {
+            let type_size = matrix_type.get_type_size() as usize;
+            let (rows_len, columns_len) = (rows as usize, columns as usize);
+            let matrix: Vec<u8> = (0..rows_len * columns_len * type_size)
+                .map(|_| rand::random())
+                .collect();
+            let mut expected = vec![0u8; matrix.len()];
+            for (k, element) in matrix.chunks_exact(type_size).enumerate() {
+                let (i, j) = (k / columns_len, k % columns_len);
+                let start = (j * rows_len + i) * type_size;
+                expected[start..start + type_size].copy_from_slice(element);
+            }
+
+            let response = Request::Reserve {
+                matrix_type,
+                matrix_rows: rows,
+                matrix_columns: columns,
+                priority: 0,
+                operation: Operation::Transpose,
+                conversion: None,
+            }
+            .execute(&job_manager, &client())
+            .await;
+            let Response::Reserve { id } = response else {
+                panic!("unexpected response to reserve: {response:?}");
+            };
+            let response = Request::Calc {
+                id,
+                matrix: Bytes::from(matrix),
+            }
+            .execute(&job_manager, &client())
+            .await;
+            assert_eq!(response, Response::Calc);
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let status = loop {
+                let response = Request::Poll { id }.execute(&job_manager, &client()).await;
+                match response {
+                    Response::Poll {
+                        status: Status::Running { .. },
+                    } if Instant::now() < deadline => {
+                        tokio::time::sleep(Duration::from_millis(1)).await
+                    }
+                    Response::Poll { status } => break status,
+                    response => panic!("unexpected response to poll: {response:?}"),
+                }
+            };
+            assert_eq!(
+                status,
+                Status::Completed {
+                    matrix_rows: columns,
+                    matrix_columns: rows,
+                    matrix_bytes: Bytes::from(expected),
+                },
+                "a {rows}x{columns} {} matrix",
+                String::from(matrix_type)
+            );
+        }
+    }
 }
@@ -0,0 +1,771 @@
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    matrix_type::{as_elements, as_elements_mut, Element, MatrixType},
+    operation::Operation,
+};
+
+// edge of the square tiles the matrix is transposed in, picked so that a source
+// tile and a destination tile fit into L1 together
+fn tile_edge<T>() -> usize {
+    if std::mem::size_of::<T>() <= 2 {
+        64
+    } else {
+        32
+    }
+}
+
+// the way a result is mirrored after it's been laid out
+#[derive(Clone, Copy, Debug)]
+struct Mirror {
+    // the last row comes first
+    rows: bool,
+    // every row is reversed
+    columns: bool,
+}
+
+impl Mirror {
+    const NONE: Mirror = Mirror::new(false, false);
+    const ROWS: Mirror = Mirror::new(true, false);
+    const COLUMNS: Mirror = Mirror::new(false, true);
+    const BOTH: Mirror = Mirror::new(true, true);
+
+    const fn new(rows: bool, columns: bool) -> Mirror {
+        Mirror { rows, columns }
+    }
+}
+
+/// Whether the operation on a `rows`x`columns` matrix is done in the buffer of
+/// the matrix itself, so no buffer has to be set aside for the result.
+pub fn in_place(operation: Operation, rows: u32, columns: u32) -> bool {
+    operation == Operation::Transpose && rows == columns
+}
+
+/// Applies the operation to a `rows`x`columns` matrix, writing the result into
+/// a new buffer unless it's done in place. Must be called from inside the
+/// thread pool. A cancelled operation stops early and returns an incomplete
+/// matrix.
+pub fn apply(
+    operation: Operation,
+    matrix_type: MatrixType,
+    mut matrix_vec: Vec<u8>,
+    rows: usize,
+    columns: usize,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    let type_size = matrix_type.get_type_size() as usize;
+    if in_place(operation, rows as u32, columns as u32) {
+        transpose_square(&mut matrix_vec, type_size, rows, cancelled);
+        return matrix_vec;
+    }
+
+    let mut result_vec = vec![0u8; matrix_vec.len()];
+    apply_into(
+        operation,
+        matrix_type,
+        &matrix_vec,
+        &mut result_vec,
+        rows,
+        columns,
+        cancelled,
+    );
+    result_vec
+}
+
+fn apply_into(
+    operation: Operation,
+    matrix_type: MatrixType,
+    matrix_vec: &[u8],
+    result_vec: &mut [u8],
+    rows: usize,
+    columns: usize,
+    cancelled: &AtomicBool,
+) {
+    let type_size = matrix_type.get_type_size() as usize;
+    // a rotation by 90 degrees is a transposition with its rows mirrored and so on
+    let (transposed, mirror) = match operation {
+        Operation::Transpose => (true, Mirror::NONE),
+        Operation::AntiTranspose => (true, Mirror::BOTH),
+        Operation::Rotate90 => (true, Mirror::COLUMNS),
+        Operation::Rotate270 => (true, Mirror::ROWS),
+        Operation::Rotate180 => (false, Mirror::BOTH),
+        Operation::FlipHorizontal => (false, Mirror::COLUMNS),
+        Operation::FlipVertical => (false, Mirror::ROWS),
+        Operation::Negate => return negate(matrix_type, matrix_vec, result_vec, cancelled),
+        Operation::Abs => return abs(matrix_type, matrix_vec, result_vec, cancelled),
+        Operation::Multiply(_) => panic!("a product has two operands, see matmul.rs"),
+        Operation::Reduce(_) => panic!("a reduction has a result of its own size, see reduce.rs"),
+    };
+    if transposed {
+        transpose_into(
+            matrix_vec, result_vec, type_size, rows, columns, mirror, cancelled,
+        );
+    } else {
+        mirror_into(
+            matrix_vec, result_vec, type_size, rows, columns, mirror, cancelled,
+        );
+    }
+}
+
+fn transpose_into(
+    matrix_vec: &[u8],
+    transposed_vec: &mut [u8],
+    type_size: usize,
+    rows: usize,
+    columns: usize,
+    mirror: Mirror,
+    cancelled: &AtomicBool,
+) {
+    match type_size {
+        1 => transpose_blocked::<u8>(matrix_vec, transposed_vec, rows, columns, mirror, cancelled),
+        2 => {
+            transpose_typed::<u16, 2>(matrix_vec, transposed_vec, rows, columns, mirror, cancelled)
+        }
+        4 => {
+            transpose_typed::<u32, 4>(matrix_vec, transposed_vec, rows, columns, mirror, cancelled)
+        }
+        8 => {
+            transpose_typed::<u64, 8>(matrix_vec, transposed_vec, rows, columns, mirror, cancelled)
+        }
+        _ => panic!("unsupported type size: {type_size}"),
+    };
+}
+
+fn transpose_typed<T: Element, const N: usize>(
+    matrix_vec: &[u8],
+    transposed_vec: &mut [u8],
+    rows: usize,
+    columns: usize,
+    mirror: Mirror,
+    cancelled: &AtomicBool,
+) {
+    if let (Some(src), Some(dst)) = (
+        as_elements::<T>(matrix_vec),
+        as_elements_mut::<T>(transposed_vec),
+    ) {
+        return transpose_blocked(src, dst, rows, columns, mirror, cancelled);
+    }
+
+    // a Vec<u8> doesn't have to be aligned for T, so fall back to byte arrays,
+    // which are always aligned.
+    // SAFETY: any bit pattern is a valid byte array
+    let (_, src, _) = unsafe { matrix_vec.align_to::<[u8; N]>() };
+    let (_, dst, _) = unsafe { transposed_vec.align_to_mut::<[u8; N]>() };
+    transpose_blocked(src, dst, rows, columns, mirror, cancelled)
+}
+
+fn transpose_blocked<T: Copy + Send + Sync>(
+    src: &[T],
+    dst: &mut [T],
+    rows: usize,
+    columns: usize,
+    mirror: Mirror,
+    cancelled: &AtomicBool,
+) {
+    if dst.is_empty() {
+        return;
+    }
+
+    // every worker owns a band of `tile` destination rows, i.e. `tile` source columns,
+    // and walks down the source matrix one tile x tile block at a time. The source is
+    // only read, so no two workers ever hold overlapping mutable borrows
+    let tile = tile_edge::<T>();
+    let _ = dst
+        .par_chunks_mut(tile * rows)
+        .enumerate()
+        .try_for_each(|(band, dst_band)| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            let band_columns = dst_band.len() / rows;
+            // with the rows mirrored the band starts from the last source columns
+            let first_column = match mirror.rows {
+                false => band * tile,
+                true => columns - band * tile - band_columns,
+            };
+            for first_row in (0..rows).step_by(tile) {
+                let last_row = (first_row + tile).min(rows);
+                // the block is read down its source columns, which stay in the
+                // cache, and written along its destination rows
+                for (k, dst_row) in dst_band.chunks_exact_mut(rows).enumerate() {
+                    let src_column = match mirror.rows {
+                        false => first_column + k,
+                        true => first_column + band_columns - 1 - k,
+                    };
+                    for i in first_row..last_row {
+                        let dst_column = match mirror.columns {
+                            false => i,
+                            true => rows - 1 - i,
+                        };
+                        dst_row[dst_column] = src[i * columns + src_column];
+                    }
+                }
+            }
+            Ok(())
+        });
+}
+
+fn transpose_square(matrix_vec: &mut [u8], type_size: usize, dim: usize, cancelled: &AtomicBool) {
+    match type_size {
+        1 => transpose_square_tiled::<u8>(matrix_vec, dim, cancelled),
+        2 => transpose_square_typed::<u16, 2>(matrix_vec, dim, cancelled),
+        4 => transpose_square_typed::<u32, 4>(matrix_vec, dim, cancelled),
+        8 => transpose_square_typed::<u64, 8>(matrix_vec, dim, cancelled),
+        _ => panic!("unsupported type size: {type_size}"),
+    };
+}
+
+fn transpose_square_typed<T: Element, const N: usize>(
+    matrix_vec: &mut [u8],
+    dim: usize,
+    cancelled: &AtomicBool,
+) {
+    if let Some(elements) = as_elements_mut::<T>(matrix_vec) {
+        return transpose_square_tiled(elements, dim, cancelled);
+    }
+
+    // SAFETY: byte arrays are always aligned and any bit pattern is valid for them
+    let (_, elements, _) = unsafe { matrix_vec.align_to_mut::<[u8; N]>() };
+    transpose_square_tiled(elements, dim, cancelled)
+}
+
+fn transpose_square_tiled<T: Send>(elements: &mut [T], dim: usize, cancelled: &AtomicBool) {
+    if elements.is_empty() {
+        return;
+    }
+
+    // the matrix is cut into bands of `tile` rows. Every band swaps its tiles
+    // right of the diagonal with the tiles of the bands below it, which are
+    // split off the matrix, so the swaps of one band run in parallel without
+    // any two of them borrowing the same tile
+    let tile = tile_edge::<T>();
+    let mut rest = elements;
+    for band in 0..dim.div_ceil(tile) {
+        if cancelled.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let band_rows = tile.min(dim - band * tile);
+        let (band_vec, below) = rest.split_at_mut(band_rows * dim);
+        rest = below;
+
+        // the pieces of the band's rows, one list per tile column
+        let mut tiles: Vec<Vec<&mut [T]>> = (0..dim.div_ceil(tile))
+            .map(|_| Vec::with_capacity(band_rows))
+            .collect();
+        for row in band_vec.chunks_mut(dim) {
+            for (pieces, piece) in tiles.iter_mut().zip(row.chunks_mut(tile)) {
+                pieces.push(piece);
+            }
+        }
+        let mut tiles = tiles.into_iter().skip(band);
+
+        // the tile on the diagonal is transposed within itself
+        let mut diagonal = tiles.next().unwrap();
+        for i in 0..band_rows {
+            let (upper, lower) = diagonal.split_at_mut(i + 1);
+            for (k, row) in lower.iter_mut().enumerate() {
+                std::mem::swap(&mut upper[i][i + 1 + k], &mut row[i]);
+            }
+        }
+
+        let first_column = band * tile;
+        let _ = tiles
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .zip(rest.par_chunks_mut(tile * dim))
+            .try_for_each(|(mut pieces, lower_band)| {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(());
+                }
+
+                // the element (i, j) of the tile is swapped with (j, i) of its
+                // mirror image in the lower band
+                for (i, piece) in pieces.iter_mut().enumerate() {
+                    for (j, element) in piece.iter_mut().enumerate() {
+                        std::mem::swap(element, &mut lower_band[j * dim + first_column + i]);
+                    }
+                }
+                Ok(())
+            });
+    }
+}
+
+fn mirror_into(
+    matrix_vec: &[u8],
+    mirrored_vec: &mut [u8],
+    type_size: usize,
+    rows: usize,
+    columns: usize,
+    mirror: Mirror,
+    cancelled: &AtomicBool,
+) {
+    match type_size {
+        1 => mirror_rows::<1>(matrix_vec, mirrored_vec, rows, columns, mirror, cancelled),
+        2 => mirror_rows::<2>(matrix_vec, mirrored_vec, rows, columns, mirror, cancelled),
+        4 => mirror_rows::<4>(matrix_vec, mirrored_vec, rows, columns, mirror, cancelled),
+        8 => mirror_rows::<8>(matrix_vec, mirrored_vec, rows, columns, mirror, cancelled),
+        _ => panic!("unsupported type size: {type_size}"),
+    };
+}
+
+fn mirror_rows<const N: usize>(
+    matrix_vec: &[u8],
+    mirrored_vec: &mut [u8],
+    rows: usize,
+    columns: usize,
+    mirror: Mirror,
+    cancelled: &AtomicBool,
+) {
+    if mirrored_vec.is_empty() {
+        return;
+    }
+
+    // SAFETY: byte arrays are always aligned and any bit pattern is valid for them
+    let (_, src, _) = unsafe { matrix_vec.align_to::<[u8; N]>() };
+    let (_, dst, _) = unsafe { mirrored_vec.align_to_mut::<[u8; N]>() };
+    let _ = dst
+        .par_chunks_mut(columns)
+        .enumerate()
+        .try_for_each(|(i, dst_row)| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            let src_i = match mirror.rows {
+                false => i,
+                true => rows - 1 - i,
+            };
+            let src_row = &src[src_i * columns..][..columns];
+            if mirror.columns {
+                for (dst, &src) in dst_row.iter_mut().zip(src_row.iter().rev()) {
+                    *dst = src;
+                }
+            } else {
+                dst_row.copy_from_slice(src_row);
+            }
+            Ok(())
+        });
+}
+
+// integers wrap around, so the negation of the smallest signed integer is itself
+// and so is its absolute value. Floats only have their sign bit changed
+fn negate(matrix_type: MatrixType, src: &[u8], dst: &mut [u8], cancelled: &AtomicBool) {
+    match matrix_type {
+        MatrixType::U8 => map_elements(src, dst, cancelled, u8::wrapping_neg),
+        MatrixType::U16 => map_elements(src, dst, cancelled, u16::wrapping_neg),
+        MatrixType::U32 => map_elements(src, dst, cancelled, u32::wrapping_neg),
+        MatrixType::U64 => map_elements(src, dst, cancelled, u64::wrapping_neg),
+        MatrixType::I8 => map_elements(src, dst, cancelled, i8::wrapping_neg),
+        MatrixType::I16 => map_elements(src, dst, cancelled, i16::wrapping_neg),
+        MatrixType::I32 => map_elements(src, dst, cancelled, i32::wrapping_neg),
+        MatrixType::I64 => map_elements(src, dst, cancelled, i64::wrapping_neg),
+        MatrixType::F32 => map_elements(src, dst, cancelled, |x: f32| -x),
+        MatrixType::F64 => map_elements(src, dst, cancelled, |x: f64| -x),
+    }
+}
+
+fn abs(matrix_type: MatrixType, src: &[u8], dst: &mut [u8], cancelled: &AtomicBool) {
+    match matrix_type {
+        MatrixType::U8 | MatrixType::U16 | MatrixType::U32 | MatrixType::U64 => {
+            dst.copy_from_slice(src)
+        }
+        MatrixType::I8 => map_elements(src, dst, cancelled, i8::wrapping_abs),
+        MatrixType::I16 => map_elements(src, dst, cancelled, i16::wrapping_abs),
+        MatrixType::I32 => map_elements(src, dst, cancelled, i32::wrapping_abs),
+        MatrixType::I64 => map_elements(src, dst, cancelled, i64::wrapping_abs),
+        MatrixType::F32 => map_elements(src, dst, cancelled, f32::abs),
+        MatrixType::F64 => map_elements(src, dst, cancelled, f64::abs),
+    }
+}
+
+fn map_elements<T: Element>(
+    src: &[u8],
+    dst: &mut [u8],
+    cancelled: &AtomicBool,
+    f: impl Fn(T) -> T + Sync,
+) {
+    // elements per task, enough to outweigh the cost of scheduling it
+    const CHUNK_ELEMENTS: usize = 1 << 14;
+
+    let _ = dst
+        .par_chunks_mut(CHUNK_ELEMENTS * T::SIZE)
+        .zip(src.par_chunks(CHUNK_ELEMENTS * T::SIZE))
+        .try_for_each(|(dst_chunk, src_chunk)| {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(());
+            }
+
+            let elements = dst_chunk
+                .chunks_exact_mut(T::SIZE)
+                .zip(src_chunk.chunks_exact(T::SIZE));
+            for (dst, src) in elements {
+                f(T::read(src)).write(dst);
+            }
+            Ok(())
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an element by element transposition into a new buffer, the reference the
+    // kernels are checked against
+    fn transpose_elementwise(
+        matrix_vec: &[u8],
+        type_size: usize,
+        rows: usize,
+        columns: usize,
+    ) -> Vec<u8> {
+        let mut transposed_vec = vec![0u8; matrix_vec.len()];
+        transposed_vec
+            .par_chunks_exact_mut(type_size)
+            .enumerate()
+            .map(|(k, ch)| (k % rows, k / rows, ch))
+            .for_each(|(i, j, ch)| {
+                let offset = (i * columns + j) * type_size;
+                ch.copy_from_slice(&matrix_vec[offset..offset + type_size])
+            });
+        transposed_vec
+    }
+
+    // the transposition the server started with, rewritten without raw pointers:
+    // every element right of the diagonal is swapped with its mirror image in
+    // the rows below, one element at a time. Kept as the baseline the kernels
+    // are benchmarked against
+    fn transpose_in_place_elementwise(matrix_vec: &mut [u8], type_size: usize, dim: usize) {
+        for i in 0..dim {
+            let (upper, lower) = matrix_vec.split_at_mut((i + 1) * dim * type_size);
+            let row = &mut upper[(i * dim + i + 1) * type_size..];
+            row.par_chunks_exact_mut(type_size)
+                .zip(lower.par_chunks_exact_mut(dim * type_size))
+                .for_each(|(element, lower_row)| {
+                    element.swap_with_slice(&mut lower_row[i * type_size..(i + 1) * type_size])
+                });
+        }
+    }
+
+    fn unsigned(type_size: usize) -> MatrixType {
+        match type_size {
+            1 => MatrixType::U8,
+            2 => MatrixType::U16,
+            4 => MatrixType::U32,
+            _ => MatrixType::U64,
+        }
+    }
+
+    // moves every element to where the operation puts it, one at a time
+    fn rearrange_elementwise(
+        operation: Operation,
+        matrix_vec: &[u8],
+        type_size: usize,
+        rows: usize,
+        columns: usize,
+    ) -> Vec<u8> {
+        let (result_rows, result_columns) = operation.output_shape(rows as u32, columns as u32);
+        let mut result_vec = vec![0u8; matrix_vec.len()];
+        for i in 0..result_rows as usize {
+            for j in 0..result_columns as usize {
+                let (src_i, src_j) = match operation {
+                    Operation::Transpose => (j, i),
+                    Operation::AntiTranspose => (rows - 1 - j, columns - 1 - i),
+                    Operation::Rotate90 => (rows - 1 - j, i),
+                    Operation::Rotate180 => (rows - 1 - i, columns - 1 - j),
+                    Operation::Rotate270 => (j, columns - 1 - i),
+                    Operation::FlipHorizontal => (i, columns - 1 - j),
+                    Operation::FlipVertical => (rows - 1 - i, j),
+                    Operation::Negate
+                    | Operation::Abs
+                    | Operation::Multiply(_)
+                    | Operation::Reduce(_) => unreachable!(),
+                };
+                let src = (src_i * columns + src_j) * type_size;
+                let dst = (i * result_columns as usize + j) * type_size;
+                result_vec[dst..dst + type_size].copy_from_slice(&matrix_vec[src..src + type_size]);
+            }
+        }
+        result_vec
+    }
+
+    #[test]
+    fn rearrangements_against_elementwise() {
+        let cancelled = AtomicBool::new(false);
+        let operations = Operation::UNARY
+            .into_iter()
+            .filter(|operation| !matches!(operation, Operation::Negate | Operation::Abs));
+
+        for operation in operations {
+            for type_size in [1, 2, 4, 8] {
+                // the larger ones don't fit into a single tile
+                for (rows, columns) in [(0, 3), (3, 0), (1, 1), (2, 5), (4, 3), (70, 45), (33, 129)]
+                {
+                    let len = type_size * rows * columns;
+                    // one extra byte to also run the kernels on misaligned buffers
+                    let orig_vec: Vec<u8> = (0..len + 1).map(|_| rand::random()).collect();
+                    let mut result_vec = vec![0u8; len + 1];
+
+                    for offset in [0, 1] {
+                        let matrix_vec = &orig_vec[offset..offset + len];
+                        apply_into(
+                            operation,
+                            unsigned(type_size),
+                            matrix_vec,
+                            &mut result_vec[offset..offset + len],
+                            rows,
+                            columns,
+                            &cancelled,
+                        );
+                        assert_eq!(
+                            &result_vec[offset..offset + len],
+                            rearrange_elementwise(operation, matrix_vec, type_size, rows, columns),
+                            "results differ for {} of a {rows}x{columns} matrix of type_size {type_size} bytes",
+                            String::from(operation)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn map<T: Copy>(
+        operation: Operation,
+        matrix_type: MatrixType,
+        elements: &[T],
+        to_bytes: fn(T) -> Vec<u8>,
+    ) -> Vec<u8> {
+        let matrix_vec: Vec<u8> = elements.iter().flat_map(|&e| to_bytes(e)).collect();
+        let cancelled = AtomicBool::new(false);
+        apply(
+            operation,
+            matrix_type,
+            matrix_vec,
+            1,
+            elements.len(),
+            &cancelled,
+        )
+    }
+
+    fn bytes<T: Copy>(elements: &[T], to_bytes: fn(T) -> Vec<u8>) -> Vec<u8> {
+        elements.iter().flat_map(|&e| to_bytes(e)).collect()
+    }
+
+    #[test]
+    fn negate_and_abs() {
+        let u8s: fn(u8) -> Vec<u8> = |e| e.to_le_bytes().to_vec();
+        let i16s: fn(i16) -> Vec<u8> = |e| e.to_le_bytes().to_vec();
+        let u64s: fn(u64) -> Vec<u8> = |e| e.to_le_bytes().to_vec();
+        let f32s: fn(f32) -> Vec<u8> = |e| e.to_le_bytes().to_vec();
+
+        let elements = [0, 1, 200, u8::MAX];
+        assert_eq!(
+            map(Operation::Negate, MatrixType::U8, &elements, u8s),
+            bytes(&[0, 255, 56, 1], u8s)
+        );
+        assert_eq!(
+            map(Operation::Abs, MatrixType::U8, &elements, u8s),
+            bytes(&elements, u8s)
+        );
+
+        let elements = [0, 1, -300, i16::MAX, i16::MIN];
+        assert_eq!(
+            map(Operation::Negate, MatrixType::I16, &elements, i16s),
+            bytes(&[0, -1, 300, -i16::MAX, i16::MIN], i16s)
+        );
+        assert_eq!(
+            map(Operation::Abs, MatrixType::I16, &elements, i16s),
+            bytes(&[0, 1, 300, i16::MAX, i16::MIN], i16s)
+        );
+
+        let elements = [1, u64::MAX];
+        assert_eq!(
+            map(Operation::Negate, MatrixType::U64, &elements, u64s),
+            bytes(&[u64::MAX, 1], u64s)
+        );
+
+        let elements = [1.5, -0.0, f32::NEG_INFINITY, f32::NAN];
+        assert_eq!(
+            map(Operation::Negate, MatrixType::F32, &elements, f32s),
+            bytes(&[-1.5, 0.0, f32::INFINITY, -f32::NAN], f32s)
+        );
+        assert_eq!(
+            map(Operation::Abs, MatrixType::F32, &elements, f32s),
+            bytes(&[1.5, 0.0, f32::INFINITY, f32::NAN], f32s)
+        );
+    }
+
+    // small enough to run under Miri: cargo +nightly miri test transpose_small_matrices
+    #[test]
+    fn transpose_small_matrices() {
+        let cancelled = AtomicBool::new(false);
+
+        for type_size in [1, 2, 4, 8] {
+            for rows in 0..5 {
+                for columns in 0..5 {
+                    let len = type_size * rows * columns;
+                    // one extra byte to also run the kernels on misaligned buffers
+                    let orig_vec: Vec<u8> = (0..len + 1).map(|b| b as u8).collect();
+                    let mut transposed_vec = vec![0u8; len + 1];
+
+                    for offset in [0, 1] {
+                        let matrix_vec = &orig_vec[offset..offset + len];
+                        transpose_into(
+                            matrix_vec,
+                            &mut transposed_vec[offset..offset + len],
+                            type_size,
+                            rows,
+                            columns,
+                            Mirror::NONE,
+                            &cancelled,
+                        );
+                        assert_eq!(
+                            &transposed_vec[offset..offset + len],
+                            transpose_elementwise(matrix_vec, type_size, rows, columns),
+                            "results differ for a {rows}x{columns} matrix of type_size {type_size} bytes"
+                        );
+
+                        if rows == columns {
+                            let mut square_vec = orig_vec.clone();
+                            transpose_square(
+                                &mut square_vec[offset..offset + len],
+                                type_size,
+                                rows,
+                                &cancelled,
+                            );
+                            assert_eq!(
+                                &square_vec[offset..offset + len],
+                                &transposed_vec[offset..offset + len],
+                                "results differ for a {rows}x{rows} matrix of type_size {type_size} bytes transposed in place"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn square_matrices_in_place() {
+        let cancelled = AtomicBool::new(false);
+
+        for type_size in [1, 2, 4, 8] {
+            // whole tiles and partial ones, the edge is 64 or 32
+            for dim in [31, 32, 33, 64, 65, 130] {
+                let len = type_size * dim * dim;
+                // one extra byte to also run the kernels on misaligned buffers
+                let orig_vec: Vec<u8> = (0..len + 1).map(|_| rand::random()).collect();
+
+                for offset in [0, 1] {
+                    let matrix_vec = &orig_vec[offset..offset + len];
+                    let mut square_vec = orig_vec.clone();
+                    transpose_square(
+                        &mut square_vec[offset..offset + len],
+                        type_size,
+                        dim,
+                        &cancelled,
+                    );
+                    assert_eq!(
+                        &square_vec[offset..offset + len],
+                        transpose_elementwise(matrix_vec, type_size, dim, dim),
+                        "results differ for a {dim}x{dim} matrix of type_size {type_size} bytes"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blocked_against_elementwise() {
+        let num_threads = std::thread::available_parallelism().unwrap().get();
+        let tp = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .unwrap();
+        let cancelled = AtomicBool::new(false);
+
+        for type_size in [1, 2, 4, 8] {
+            for dim in [10, 100, 1000] {
+                let orig_vec: Vec<u8> =
+                    (0..type_size * dim * dim).map(|_| rand::random()).collect();
+
+                let mut elementwise_vec = orig_vec.clone();
+                let begin_time = std::time::Instant::now();
+                tp.install(|| transpose_in_place_elementwise(&mut elementwise_vec, type_size, dim));
+                let elementwise_duration = begin_time.elapsed();
+
+                let matrix_vec = orig_vec.clone();
+                let begin_time = std::time::Instant::now();
+                let matrix_type = unsigned(type_size);
+                let blocked_vec = tp.install(|| {
+                    apply(
+                        Operation::Transpose,
+                        matrix_type,
+                        matrix_vec,
+                        dim,
+                        dim,
+                        &cancelled,
+                    )
+                });
+                let blocked_duration = begin_time.elapsed();
+
+                let expected_vec = transpose_elementwise(&orig_vec, type_size, dim, dim);
+                assert!(
+                    elementwise_vec == expected_vec && blocked_vec == expected_vec,
+                    "results differ for a {dim}x{dim} matrix of type_size {type_size} bytes"
+                );
+                println!(
+                    "type_size {} bytes - dim {:>6} - {} threads - elementwise {:>12} ns - blocked {:>12} ns - {:.2}x",
+                    type_size,
+                    dim,
+                    num_threads,
+                    elementwise_duration.as_nanos(),
+                    blocked_duration.as_nanos(),
+                    elementwise_duration.as_nanos() as f64 / blocked_duration.as_nanos() as f64
+                );
+            }
+
+            // a matrix that isn't square is transposed into a new buffer
+            for (rows, columns) in [(100, 150), (1000, 1500), (3000, 700)] {
+                let orig_vec: Vec<u8> = (0..type_size * rows * columns)
+                    .map(|_| rand::random())
+                    .collect();
+
+                let begin_time = std::time::Instant::now();
+                let expected_vec =
+                    tp.install(|| transpose_elementwise(&orig_vec, type_size, rows, columns));
+                let elementwise_duration = begin_time.elapsed();
+
+                let matrix_vec = orig_vec.clone();
+                let begin_time = std::time::Instant::now();
+                let matrix_type = unsigned(type_size);
+                let blocked_vec = tp.install(|| {
+                    apply(
+                        Operation::Transpose,
+                        matrix_type,
+                        matrix_vec,
+                        rows,
+                        columns,
+                        &cancelled,
+                    )
+                });
+                let blocked_duration = begin_time.elapsed();
+
+                assert!(
+                    blocked_vec == expected_vec,
+                    "results differ for a {rows}x{columns} matrix of type_size {type_size} bytes"
+                );
+                println!(
+                    "type_size {} bytes - {:>11} - {} threads - elementwise {:>12} ns - blocked {:>12} ns - {:.2}x",
+                    type_size,
+                    format!("{rows}x{columns}"),
+                    num_threads,
+                    elementwise_duration.as_nanos(),
+                    blocked_duration.as_nanos(),
+                    elementwise_duration.as_nanos() as f64 / blocked_duration.as_nanos() as f64
+                );
+            }
+        }
+    }
+}
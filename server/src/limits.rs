@@ -0,0 +1,188 @@
+use crate::{
+    error::{Error, ErrorCode},
+    matrix_type::MatrixType,
+    operation::Operation,
+};
+
+/// The largest matrix a single job may hold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JobLimits {
+    pub max_dimension: u32,
+    pub max_job_bytes: u64,
+}
+
+impl JobLimits {
+    /// Returns the size of the matrix in bytes, or an error if the matrix is
+    /// empty or over the limits. The size is computed without overflowing, so
+    /// any dimensions a client sends are safe to check.
+    pub fn matrix_len(
+        &self,
+        matrix_type: MatrixType,
+        matrix_rows: u32,
+        matrix_columns: u32,
+    ) -> Result<usize, Error> {
+        if matrix_rows == 0 || matrix_columns == 0 {
+            return Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("the matrix can't be empty, it is {matrix_rows}x{matrix_columns}"),
+            ));
+        }
+        if matrix_rows > self.max_dimension || matrix_columns > self.max_dimension {
+            return Err(Error::new(
+                ErrorCode::TooLarge,
+                format!(
+                    "the matrix is {matrix_rows}x{matrix_columns}, the limit is {} in each dimension",
+                    self.max_dimension
+                ),
+            ));
+        }
+
+        let too_large = || {
+            Error::new(
+                ErrorCode::TooLarge,
+                format!(
+                    "a {matrix_rows}x{matrix_columns} matrix of {} byte elements is over the limit of {} bytes",
+                    matrix_type.get_type_size(),
+                    self.max_job_bytes
+                ),
+            )
+        };
+        let len = (matrix_type.get_type_size() as u64)
+            .checked_mul(matrix_rows as u64)
+            .and_then(|len| len.checked_mul(matrix_columns as u64))
+            .filter(|&len| len <= self.max_job_bytes)
+            .ok_or_else(too_large)?;
+        // the whole matrix has to be addressable in memory
+        usize::try_from(len).map_err(|_| too_large())
+    }
+
+    /// Returns the size of everything a job's client uploads: the matrix, or
+    /// both operands of a product, which together are held to the same limit.
+    pub fn input_len(
+        &self,
+        matrix_type: MatrixType,
+        matrix_rows: u32,
+        matrix_columns: u32,
+        operation: Operation,
+    ) -> Result<usize, Error> {
+        let len = self.matrix_len(matrix_type, matrix_rows, matrix_columns)?;
+        let Operation::Multiply(product) = operation else {
+            return Ok(len);
+        };
+
+        let right_len = self.matrix_len(matrix_type, matrix_columns, product.columns)?;
+        len.checked_add(right_len)
+            .filter(|&len| len as u64 <= self.max_job_bytes)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorCode::TooLarge,
+                    format!(
+                        "the operands are {len} and {right_len} bytes long, together over the limit of {} bytes",
+                        self.max_job_bytes
+                    ),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::{Arithmetic, Product};
+
+    const UNLIMITED: JobLimits = JobLimits {
+        max_dimension: u32::MAX,
+        max_job_bytes: u64::MAX,
+    };
+
+    #[test]
+    fn computes_lengths() {
+        assert_eq!(UNLIMITED.matrix_len(MatrixType::U8, 1, 1), Ok(1));
+        assert_eq!(UNLIMITED.matrix_len(MatrixType::F64, 2, 3), Ok(48));
+    }
+
+    #[test]
+    fn rejects_empty_matrices() {
+        for (rows, columns) in [(0, 0), (0, 1), (1, 0), (0, u32::MAX)] {
+            let error = UNLIMITED
+                .matrix_len(MatrixType::U8, rows, columns)
+                .unwrap_err();
+            assert_eq!(error.code, ErrorCode::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn the_largest_dimensions_dont_overflow() {
+        // 8 * (2^32 - 1)^2 is just under 2^67
+        let error = UNLIMITED
+            .matrix_len(MatrixType::U64, u32::MAX, u32::MAX)
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooLarge);
+
+        // (2^32 - 1)^2 fits into a u64, but not into the limit
+        let limits = JobLimits {
+            max_dimension: u32::MAX,
+            max_job_bytes: 1 << 40,
+        };
+        let error = limits
+            .matrix_len(MatrixType::U8, u32::MAX, u32::MAX)
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooLarge);
+        assert_eq!(
+            limits.matrix_len(MatrixType::U8, u32::MAX, 1),
+            Ok(u32::MAX as usize)
+        );
+    }
+
+    #[test]
+    fn checks_the_limits() {
+        let limits = JobLimits {
+            max_dimension: 100,
+            max_job_bytes: 800,
+        };
+
+        assert_eq!(limits.matrix_len(MatrixType::U8, 100, 8), Ok(800));
+        assert_eq!(limits.matrix_len(MatrixType::U64, 10, 10), Ok(800));
+        for (matrix_type, rows, columns) in [
+            (MatrixType::U8, 101, 1),
+            (MatrixType::U8, 1, 101),
+            (MatrixType::U8, 9, 100),
+            (MatrixType::U16, 100, 5),
+        ] {
+            let error = limits.matrix_len(matrix_type, rows, columns).unwrap_err();
+            assert_eq!(error.code, ErrorCode::TooLarge);
+        }
+    }
+
+    #[test]
+    fn counts_both_operands_of_a_product() {
+        let limits = JobLimits {
+            max_dimension: 100,
+            max_job_bytes: 800,
+        };
+        let multiply = |columns| {
+            Operation::Multiply(Product {
+                columns,
+                arithmetic: Arithmetic::Wrapping,
+            })
+        };
+
+        assert_eq!(
+            limits.input_len(MatrixType::U8, 10, 20, Operation::Rotate90),
+            Ok(200)
+        );
+        assert_eq!(
+            limits.input_len(MatrixType::U8, 10, 20, multiply(30)),
+            Ok(800)
+        );
+        // each operand is within the limit, but not both of them
+        let error = limits
+            .input_len(MatrixType::U8, 10, 20, multiply(31))
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::TooLarge);
+        let error = limits
+            .input_len(MatrixType::U8, 10, 20, multiply(0))
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidArgument);
+    }
+}
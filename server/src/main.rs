@@ -1,25 +1,40 @@
+mod codec;
+mod config;
+mod convert;
+mod error;
+mod handshake;
 mod job;
+mod kernel;
+mod limits;
+mod matmul;
 mod matrix_type;
+mod memory;
+mod operation;
+mod queue;
+mod quota;
+mod reduce;
 mod request;
 mod response;
+mod scheduler;
 mod status;
 mod thread;
+mod token;
+mod upload;
 
+use std::sync::Arc;
 use sysinfo::{CpuRefreshKind, RefreshKind, System, SystemExt};
 use tokio::net::TcpListener;
 
+use crate::config::Config;
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
     const ADDR: &str = "127.0.0.1";
 
-    let args: Vec<String> = std::env::args().collect();
-    let port = match args.len() {
-        1 => "0",
-        2 => &args[1],
-        _ => Err("there can be only one argument: the port number")?,
-    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = Config::from_args(&args)?;
 
-    let listener = TcpListener::bind(format!("{ADDR}:{port}"))
+    let listener = TcpListener::bind(format!("{ADDR}:{}", config.port))
         .await
         .map_err(|e| e.to_string())?;
     let port = listener.local_addr().unwrap().port();
@@ -34,10 +49,39 @@ async fn main() -> Result<(), String> {
         .build()
         .unwrap();
 
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-    tokio::task::spawn(job::process_tasks(tp, rx));
+    let max_concurrent_jobs = config.max_concurrent_jobs.unwrap_or(cpu_count);
+
+    let (tx, rx) = tokio::sync::mpsc::channel(config.max_queued_jobs);
+    let queue = Arc::new(queue::JobQueue::new(config.max_queued_jobs));
+    tokio::task::spawn(job::process_tasks(
+        tp,
+        rx,
+        max_concurrent_jobs,
+        config.scheduling,
+        Arc::clone(&queue),
+    ));
+
+    let config = Arc::new(config);
+    let memory = Arc::new(memory::MemoryLedger::new(config.memory_cap));
+    tokio::task::spawn(memory::refresh_memory(Arc::clone(&memory)));
+
+    let job_manager = Arc::new(job::new_manager(
+        tx,
+        config.upload_grace_period,
+        config.result_ttl,
+        Arc::clone(&memory),
+        config.job_limits(),
+        Arc::new(quota::QuotaLedger::new(config.quota)),
+        queue,
+        config.max_frame_len,
+    ));
+    tokio::task::spawn(job::expire_tasks(Arc::clone(&job_manager)));
     loop {
         let (stream, _) = listener.accept().await.map_err(|e| e.to_string())?;
-        tokio::spawn(thread::handle_client(stream, tx.clone()));
+        tokio::spawn(thread::handle_client(
+            stream,
+            Arc::clone(&job_manager),
+            Arc::clone(&config),
+        ));
     }
 }
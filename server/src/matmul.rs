@@ -0,0 +1,246 @@
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    matrix_type::{with_element, Element, MatrixType},
+    operation::{Arithmetic, Product},
+};
+
+// the result rows a single worker computes
+const BAND_ROWS: usize = 8;
+// a block of the right operand is at most 128x256 elements, which fits into L2
+// even with 8-byte elements
+const BLOCK_INNER: usize = 128;
+const BLOCK_COLUMNS: usize = 256;
+
+trait MultiplyAdd: Element {
+    // adds a * b to the sum
+    fn multiply_add(self, a: Self, b: Self, arithmetic: Arithmetic) -> Self;
+}
+
+macro_rules! integer {
+    ($($element:ty),*) => {$(
+        impl MultiplyAdd for $element {
+            fn multiply_add(self, a: Self, b: Self, arithmetic: Arithmetic) -> Self {
+                match arithmetic {
+                    Arithmetic::Wrapping => self.wrapping_add(a.wrapping_mul(b)),
+                    Arithmetic::Saturating => self.saturating_add(a.saturating_mul(b)),
+                }
+            }
+        }
+    )*};
+}
+
+macro_rules! float {
+    ($($element:ty),*) => {$(
+        impl MultiplyAdd for $element {
+            // not fused, so that the result doesn't depend on the CPU
+            fn multiply_add(self, a: Self, b: Self, _: Arithmetic) -> Self {
+                self + a * b
+            }
+        }
+    )*};
+}
+
+integer!(u8, u16, u32, u64, i8, i16, i32, i64);
+float!(f32, f64);
+
+/// Multiplies the `rows`x`inner` left operand by the right operand that
+/// follows it in `operands`. Must be called from inside the thread pool. A
+/// cancelled product stops early and returns an incomplete matrix.
+pub fn multiply(
+    matrix_type: MatrixType,
+    operands: &[u8],
+    rows: usize,
+    inner: usize,
+    product: Product,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    let columns = product.columns as usize;
+    let arithmetic = product.arithmetic;
+    with_element!(matrix_type, T => {
+        multiply_typed::<T>(operands, rows, inner, columns, arithmetic, cancelled)
+    })
+}
+
+fn multiply_typed<T: MultiplyAdd>(
+    operands: &[u8],
+    rows: usize,
+    inner: usize,
+    columns: usize,
+    arithmetic: Arithmetic,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    let (left, right) = operands.split_at(rows * inner * T::SIZE);
+    let mut result_vec = vec![0u8; rows * columns * T::SIZE];
+    if result_vec.is_empty() {
+        return result_vec;
+    }
+
+    // every worker owns a band of result rows and sums into a typed copy of it.
+    // Every element of the result is summed in the order of the inner index,
+    // so saturation and float rounding don't depend on the blocking
+    let _ = result_vec
+        .par_chunks_mut(BAND_ROWS * columns * T::SIZE)
+        .enumerate()
+        .try_for_each(|(band, dst_band)| {
+            let first_row = band * BAND_ROWS;
+            let band_rows = dst_band.len() / (columns * T::SIZE);
+            let mut sums = vec![T::default(); band_rows * columns];
+
+            for first_column in (0..columns).step_by(BLOCK_COLUMNS) {
+                let last_column = (first_column + BLOCK_COLUMNS).min(columns);
+                for first_k in (0..inner).step_by(BLOCK_INNER) {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return Err(());
+                    }
+
+                    let last_k = (first_k + BLOCK_INNER).min(inner);
+                    for i in 0..band_rows {
+                        let sums_row = &mut sums[i * columns..][first_column..last_column];
+                        for k in first_k..last_k {
+                            let a = T::read(&left[((first_row + i) * inner + k) * T::SIZE..]);
+                            let right_row = &right[(k * columns + first_column) * T::SIZE
+                                ..(k * columns + last_column) * T::SIZE];
+                            for (sum, b) in sums_row.iter_mut().zip(right_row.chunks_exact(T::SIZE))
+                            {
+                                *sum = sum.multiply_add(a, T::read(b), arithmetic);
+                            }
+                        }
+                    }
+                }
+            }
+
+            for (sum, dst) in sums.into_iter().zip(dst_band.chunks_exact_mut(T::SIZE)) {
+                sum.write(dst);
+            }
+            Ok(())
+        });
+    result_vec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn multiply_elementwise<T: MultiplyAdd>(
+        operands: &[u8],
+        rows: usize,
+        inner: usize,
+        columns: usize,
+        arithmetic: Arithmetic,
+    ) -> Vec<u8> {
+        let (left, right) = operands.split_at(rows * inner * T::SIZE);
+        let mut result_vec = vec![0u8; rows * columns * T::SIZE];
+        for i in 0..rows {
+            for j in 0..columns {
+                let mut sum = T::default();
+                for k in 0..inner {
+                    let a = T::read(&left[(i * inner + k) * T::SIZE..]);
+                    let b = T::read(&right[(k * columns + j) * T::SIZE..]);
+                    sum = sum.multiply_add(a, b, arithmetic);
+                }
+                sum.write(&mut result_vec[(i * columns + j) * T::SIZE..][..T::SIZE]);
+            }
+        }
+        result_vec
+    }
+
+    fn against_elementwise<T: MultiplyAdd>(matrix_type: MatrixType) {
+        let cancelled = AtomicBool::new(false);
+        // the larger ones span several bands and blocks
+        for (rows, inner, columns) in [
+            (1, 1, 1),
+            (3, 5, 2),
+            (9, 130, 7),
+            (17, 3, 300),
+            (20, 200, 260),
+        ] {
+            let len = (rows * inner + inner * columns) * T::SIZE;
+            // random bytes may make NaNs, whose payloads the arithmetic needn't
+            // keep, so the floats are small multiples of a quarter instead, whose
+            // products and sums come out exact in any order
+            let operands: Vec<u8> = match matrix_type {
+                MatrixType::F32 => (0..len / T::SIZE)
+                    .flat_map(|_| (rand::random::<i8>() as f32 / 4.0).to_le_bytes())
+                    .collect(),
+                MatrixType::F64 => (0..len / T::SIZE)
+                    .flat_map(|_| (rand::random::<i8>() as f64 / 4.0).to_le_bytes())
+                    .collect(),
+                _ => (0..len).map(|_| rand::random()).collect(),
+            };
+
+            for arithmetic in [Arithmetic::Wrapping, Arithmetic::Saturating] {
+                let product = Product {
+                    columns: columns as u32,
+                    arithmetic,
+                };
+                assert_eq!(
+                    multiply(matrix_type, &operands, rows, inner, product, &cancelled),
+                    multiply_elementwise::<T>(&operands, rows, inner, columns, arithmetic),
+                    "products differ for {rows}x{inner} by {inner}x{columns} {} matrices",
+                    String::from(matrix_type)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn blocked_against_elementwise() {
+        against_elementwise::<u8>(MatrixType::U8);
+        against_elementwise::<u16>(MatrixType::U16);
+        against_elementwise::<u32>(MatrixType::U32);
+        against_elementwise::<u64>(MatrixType::U64);
+        against_elementwise::<i8>(MatrixType::I8);
+        against_elementwise::<i16>(MatrixType::I16);
+        against_elementwise::<i32>(MatrixType::I32);
+        against_elementwise::<i64>(MatrixType::I64);
+        against_elementwise::<f32>(MatrixType::F32);
+        against_elementwise::<f64>(MatrixType::F64);
+    }
+
+    #[test]
+    fn wraps_or_saturates() {
+        let cancelled = AtomicBool::new(false);
+        // [100 100] x [2 -3]^T
+        let operands = [100i8, 100, 2, -3].map(|e| e as u8);
+        let product = |arithmetic| Product {
+            columns: 1,
+            arithmetic,
+        };
+
+        let result = multiply(
+            MatrixType::I8,
+            &operands,
+            1,
+            2,
+            product(Arithmetic::Wrapping),
+            &cancelled,
+        );
+        assert_eq!(result, [(-100i8) as u8]);
+        // both products saturate, 200 at 127 and -300 at -128
+        let result = multiply(
+            MatrixType::I8,
+            &operands,
+            1,
+            2,
+            product(Arithmetic::Saturating),
+            &cancelled,
+        );
+        assert_eq!(result, [(-1i8) as u8]);
+
+        let operands = [1.5f32, 2.0, 4.0, -1.0]
+            .iter()
+            .flat_map(|e| e.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let result = multiply(
+            MatrixType::F32,
+            &operands,
+            1,
+            2,
+            product(Arithmetic::Saturating),
+            &cancelled,
+        );
+        assert_eq!(result, 4.0f32.to_le_bytes());
+    }
+}
@@ -1,6 +1,8 @@
 use serde::Serialize;
 
-#[derive(Clone, Copy, Serialize)]
+use crate::error::{Error, ErrorCode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 pub enum MatrixType {
     U8,
     U16,
@@ -15,6 +17,19 @@ pub enum MatrixType {
 }
 
 impl MatrixType {
+    pub const ALL: [MatrixType; 10] = [
+        MatrixType::U8,
+        MatrixType::U16,
+        MatrixType::U32,
+        MatrixType::U64,
+        MatrixType::I8,
+        MatrixType::I16,
+        MatrixType::I32,
+        MatrixType::I64,
+        MatrixType::F32,
+        MatrixType::F64,
+    ];
+
     pub fn get_type_size(&self) -> u8 {
         match self {
             MatrixType::U8 => 1,
@@ -32,7 +47,7 @@ impl MatrixType {
 }
 
 impl std::convert::TryFrom<u8> for MatrixType {
-    type Error = String;
+    type Error = Error;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
@@ -40,13 +55,16 @@ impl std::convert::TryFrom<u8> for MatrixType {
             1 => Ok(MatrixType::U16),
             2 => Ok(MatrixType::U32),
             3 => Ok(MatrixType::U64),
-            4 => Ok(MatrixType::U8),
-            5 => Ok(MatrixType::U16),
-            6 => Ok(MatrixType::U32),
-            7 => Ok(MatrixType::U64),
+            4 => Ok(MatrixType::I8),
+            5 => Ok(MatrixType::I16),
+            6 => Ok(MatrixType::I32),
+            7 => Ok(MatrixType::I64),
             8 => Ok(MatrixType::F32),
             9 => Ok(MatrixType::F64),
-            _ => Err(format!("Invalid matrix type code: {}", value)),
+            _ => Err(Error::new(
+                ErrorCode::BadTypeCode,
+                format!("Invalid matrix type code: {}", value),
+            )),
         }
     }
 }
@@ -84,3 +102,173 @@ impl std::convert::From<MatrixType> for String {
         }
     }
 }
+
+/// The Rust type of the elements of a matrix type.
+pub trait Element: Copy + Default + Send + Sync + PartialEq + std::fmt::Debug + 'static {
+    const SIZE: usize;
+
+    // elements are little-endian, like every other number in the protocol
+    fn read(bytes: &[u8]) -> Self;
+    fn write(self, bytes: &mut [u8]);
+}
+
+macro_rules! element {
+    ($($element:ty),*) => {$(
+        impl Element for $element {
+            const SIZE: usize = std::mem::size_of::<$element>();
+
+            fn read(bytes: &[u8]) -> Self {
+                <$element>::from_le_bytes(bytes[..Self::SIZE].try_into().unwrap())
+            }
+
+            fn write(self, bytes: &mut [u8]) {
+                bytes[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+element!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+/// Evaluates the body with the given name bound to the element type of the
+/// matrix type, e.g. `with_element!(matrix_type, T => sum::<T>(matrix_vec))`.
+macro_rules! with_element {
+    ($matrix_type:expr, $element:ident => $body:expr) => {
+        match $matrix_type {
+            $crate::matrix_type::MatrixType::U8 => {
+                type $element = u8;
+                $body
+            }
+            $crate::matrix_type::MatrixType::U16 => {
+                type $element = u16;
+                $body
+            }
+            $crate::matrix_type::MatrixType::U32 => {
+                type $element = u32;
+                $body
+            }
+            $crate::matrix_type::MatrixType::U64 => {
+                type $element = u64;
+                $body
+            }
+            $crate::matrix_type::MatrixType::I8 => {
+                type $element = i8;
+                $body
+            }
+            $crate::matrix_type::MatrixType::I16 => {
+                type $element = i16;
+                $body
+            }
+            $crate::matrix_type::MatrixType::I32 => {
+                type $element = i32;
+                $body
+            }
+            $crate::matrix_type::MatrixType::I64 => {
+                type $element = i64;
+                $body
+            }
+            $crate::matrix_type::MatrixType::F32 => {
+                type $element = f32;
+                $body
+            }
+            $crate::matrix_type::MatrixType::F64 => {
+                type $element = f64;
+                $body
+            }
+        }
+    };
+}
+pub(crate) use with_element;
+
+/// Views a matrix as a slice of elements without copying it. Returns None if
+/// the bytes aren't aligned for the element type, or if the machine isn't
+/// little-endian and the elements would have to be swapped.
+pub fn as_elements<T: Element>(bytes: &[u8]) -> Option<&[T]> {
+    if cfg!(target_endian = "big") {
+        return None;
+    }
+    // SAFETY: every element type is a plain number, any bit pattern is valid for it
+    let (prefix, elements, suffix) = unsafe { bytes.align_to::<T>() };
+    (prefix.is_empty() && suffix.is_empty()).then_some(elements)
+}
+
+pub fn as_elements_mut<T: Element>(bytes: &mut [u8]) -> Option<&mut [T]> {
+    if cfg!(target_endian = "big") {
+        return None;
+    }
+    // SAFETY: as above, and any elements written are valid bytes
+    let (prefix, elements, suffix) = unsafe { bytes.align_to_mut::<T>() };
+    (prefix.is_empty() && suffix.is_empty()).then_some(elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 0..10u8 {
+            let matrix_type = MatrixType::try_from(code).unwrap();
+            assert_eq!(u8::from(matrix_type), code);
+            assert_eq!(matrix_type, MatrixType::ALL[code as usize]);
+        }
+        for code in [10, u8::MAX] {
+            let error = MatrixType::try_from(code).unwrap_err();
+            assert_eq!(error.code, ErrorCode::BadTypeCode);
+        }
+
+        let names = [
+            "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64",
+        ];
+        for (matrix_type, name) in MatrixType::ALL.into_iter().zip(names) {
+            assert_eq!(String::from(matrix_type), name);
+        }
+    }
+
+    fn elements_round_trip<T: Element>(matrix_type: MatrixType, elements: &[T]) {
+        assert_eq!(T::SIZE, matrix_type.get_type_size() as usize);
+
+        // one extra byte to also read and write misaligned elements
+        let mut bytes = vec![0u8; elements.len() * T::SIZE + 1];
+        for offset in [0, 1] {
+            let matrix_vec = &mut bytes[offset..offset + elements.len() * T::SIZE];
+            for (element, dst) in elements.iter().zip(matrix_vec.chunks_exact_mut(T::SIZE)) {
+                element.write(dst);
+            }
+            let read: Vec<T> = matrix_vec.chunks_exact(T::SIZE).map(T::read).collect();
+            assert_eq!(read, elements);
+            if let Some(view) = as_elements::<T>(matrix_vec) {
+                assert_eq!(view, elements);
+            }
+        }
+    }
+
+    #[test]
+    fn elements_of_every_type() {
+        for matrix_type in MatrixType::ALL {
+            with_element!(matrix_type, T => {
+                assert_eq!(T::SIZE, matrix_type.get_type_size() as usize);
+            });
+        }
+
+        elements_round_trip(MatrixType::U8, &[0u8, 1, u8::MAX]);
+        elements_round_trip(MatrixType::U16, &[0u16, 0x1234, u16::MAX]);
+        elements_round_trip(MatrixType::U32, &[0u32, 0x1234_5678, u32::MAX]);
+        elements_round_trip(MatrixType::U64, &[0u64, 0x1234_5678_9abc_def0, u64::MAX]);
+        elements_round_trip(MatrixType::I8, &[0i8, -1, i8::MIN, i8::MAX]);
+        elements_round_trip(MatrixType::I16, &[0i16, -1, i16::MIN, i16::MAX]);
+        elements_round_trip(MatrixType::I32, &[0i32, -1, i32::MIN, i32::MAX]);
+        elements_round_trip(MatrixType::I64, &[0i64, -1, i64::MIN, i64::MAX]);
+        elements_round_trip(MatrixType::F32, &[0.0f32, -1.5, f32::MIN, f32::INFINITY]);
+        elements_round_trip(
+            MatrixType::F64,
+            &[0.0f64, -1.5, f64::MAX, f64::NEG_INFINITY],
+        );
+
+        // the least significant byte comes first
+        assert_eq!(i16::read(&[0xfe, 0xff]), -2);
+        let mut bytes = [0; 4];
+        1.0f32.write(&mut bytes);
+        assert_eq!(bytes, [0, 0, 0x80, 0x3f]);
+    }
+}
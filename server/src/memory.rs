@@ -0,0 +1,291 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sysinfo::{RefreshKind, System, SystemExt};
+
+use crate::error::{Error, ErrorCode};
+
+// memory left for the rest of the system no matter what the cap is
+pub const AVAILABLE_MEMORY_THRESHOLD: u64 = 500_000_000;
+
+/// How much memory all the jobs may hold together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryCap {
+    Bytes(u64),
+    // a share of the total memory
+    Percent(u8),
+}
+
+impl std::str::FromStr for MemoryCap {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_suffix('%') {
+            Some(percent) => match percent.parse() {
+                Ok(percent @ 1..=100) => Ok(MemoryCap::Percent(percent)),
+                _ => Err(format!("a percentage must be from 1% to 100%: {value}")),
+            },
+            None => value
+                .parse()
+                .map(MemoryCap::Bytes)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+struct LedgerState {
+    // the bytes held by all the jobs
+    reserved: u64,
+    // the part of them the jobs have already written to, which the system
+    // counts as used
+    resident: u64,
+    // what was resident at the last refresh, and so is already missing from
+    // the available memory
+    resident_at_refresh: u64,
+    total_memory: u64,
+    available_memory: u64,
+}
+
+/// Keeps count of the memory held by the jobs, so that concurrent reservations
+/// can't overcommit.
+pub struct MemoryLedger {
+    cap: MemoryCap,
+    system: Mutex<System>,
+    state: Mutex<LedgerState>,
+}
+
+impl MemoryLedger {
+    pub fn new(cap: MemoryCap) -> MemoryLedger {
+        let ledger = MemoryLedger {
+            cap,
+            system: Mutex::new(System::new_with_specifics(RefreshKind::new().with_memory())),
+            state: Mutex::new(LedgerState {
+                reserved: 0,
+                resident: 0,
+                resident_at_refresh: 0,
+                total_memory: 0,
+                available_memory: 0,
+            }),
+        };
+        ledger.refresh();
+        ledger
+    }
+
+    pub fn refresh(&self) {
+        let mut system = self.system.lock().unwrap();
+        system.refresh_memory();
+        self.record(system.total_memory(), system.available_memory());
+    }
+
+    /// Takes the system memory measured by a refresh.
+    pub fn record(&self, total_memory: u64, available_memory: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.total_memory = total_memory;
+        state.available_memory = available_memory;
+        state.resident_at_refresh = state.resident;
+    }
+
+    /// Reserves a part of the budget, which is given back when the returned
+    /// reservation is dropped.
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> Result<MemoryReservation, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        let cap = match self.cap {
+            MemoryCap::Bytes(cap) => cap,
+            MemoryCap::Percent(percent) => state.total_memory / 100 * percent as u64,
+        };
+        if state.reserved.saturating_add(bytes) > cap {
+            return Err(Error::new(
+                ErrorCode::NotEnoughMemory,
+                format!(
+                    "not enough memory: {bytes} bytes are needed, {} of {cap} bytes are in use",
+                    state.reserved
+                ),
+            ));
+        }
+
+        // the buffers of a job only take memory once they're written to, so
+        // only what was resident at the last refresh is counted as used already
+        let unaccounted = state.reserved.saturating_sub(state.resident_at_refresh);
+        if bytes
+            .saturating_add(unaccounted)
+            .saturating_add(AVAILABLE_MEMORY_THRESHOLD)
+            > state.available_memory
+        {
+            return Err(Error::new(
+                ErrorCode::NotEnoughMemory,
+                format!("not enough memory: {bytes} bytes are needed"),
+            ));
+        }
+
+        state.reserved += bytes;
+        Ok(MemoryReservation {
+            ledger: Arc::clone(self),
+            bytes,
+            resident: 0,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn reserved(&self) -> u64 {
+        self.state.lock().unwrap().reserved
+    }
+
+    fn release(&self, bytes: u64, resident: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.reserved -= bytes;
+        state.resident -= resident;
+        // the freed memory only shows up as available after the next refresh
+        state.resident_at_refresh = state.resident_at_refresh.min(state.resident);
+    }
+
+    fn add_resident(&self, bytes: u64) {
+        self.state.lock().unwrap().resident += bytes;
+    }
+}
+
+pub struct MemoryReservation {
+    ledger: Arc<MemoryLedger>,
+    bytes: u64,
+    // how much of the reservation has been written to
+    resident: u64,
+}
+
+impl MemoryReservation {
+    // gives the part of the reservation that isn't needed anymore back to the ledger
+    pub fn shrink_to(&mut self, bytes: u64) {
+        if bytes < self.bytes {
+            let resident = self.resident.min(bytes);
+            self.ledger
+                .release(self.bytes - bytes, self.resident - resident);
+            self.bytes = bytes;
+            self.resident = resident;
+        }
+    }
+
+    // records that `bytes` of the reservation have been written to
+    pub fn set_resident(&mut self, bytes: u64) {
+        let bytes = bytes.min(self.bytes);
+        if bytes > self.resident {
+            self.ledger.add_resident(bytes - self.resident);
+            self.resident = bytes;
+        }
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.ledger.release(self.bytes, self.resident);
+    }
+}
+
+pub async fn refresh_memory(ledger: Arc<MemoryLedger>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        ledger.refresh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(cap: MemoryCap, total_memory: u64, available_memory: u64) -> Arc<MemoryLedger> {
+        let ledger = MemoryLedger::new(cap);
+        ledger.record(total_memory, available_memory);
+        Arc::new(ledger)
+    }
+
+    #[test]
+    fn parses_caps() {
+        assert_eq!("1000".parse(), Ok(MemoryCap::Bytes(1000)));
+        assert_eq!("50%".parse(), Ok(MemoryCap::Percent(50)));
+        assert!("0%".parse::<MemoryCap>().is_err());
+        assert!("101%".parse::<MemoryCap>().is_err());
+        assert!("lots".parse::<MemoryCap>().is_err());
+    }
+
+    #[test]
+    fn concurrent_reservations_stay_under_the_cap() {
+        let ledger = ledger(MemoryCap::Bytes(1000), u64::MAX, u64::MAX);
+
+        let first = ledger.reserve(600).unwrap();
+        let error = ledger.reserve(600).err().unwrap();
+        assert_eq!(error.code, ErrorCode::NotEnoughMemory);
+        let second = ledger.reserve(400).unwrap();
+        assert_eq!(ledger.reserved(), 1000);
+
+        drop(first);
+        assert_eq!(ledger.reserved(), 400);
+        drop(second);
+        assert_eq!(ledger.reserved(), 0);
+    }
+
+    #[test]
+    fn percent_of_the_total_memory() {
+        let ledger = ledger(MemoryCap::Percent(10), 100_000, u64::MAX);
+
+        assert!(ledger.reserve(10_001).is_err());
+        let _reservation = ledger.reserve(10_000).unwrap();
+    }
+
+    #[test]
+    fn reservations_count_against_available_memory() {
+        let available_memory = AVAILABLE_MEMORY_THRESHOLD + 1000;
+        let ledger = ledger(MemoryCap::Bytes(u64::MAX), u64::MAX, available_memory);
+
+        let _first = ledger.reserve(600).unwrap();
+        assert!(ledger.reserve(600).is_err());
+        let _second = ledger.reserve(400).unwrap();
+    }
+
+    #[test]
+    fn reservations_still_count_after_a_refresh() {
+        let available_memory = AVAILABLE_MEMORY_THRESHOLD + 1000;
+        let ledger = ledger(MemoryCap::Bytes(u64::MAX), u64::MAX, available_memory);
+
+        let first = ledger.reserve(600).unwrap();
+        // the job hasn't written anything yet, so the refresh sees the same memory
+        ledger.record(u64::MAX, available_memory);
+        assert!(ledger.reserve(600).is_err());
+        let _second = ledger.reserve(400).unwrap();
+
+        drop(first);
+        let _third = ledger.reserve(600).unwrap();
+    }
+
+    #[test]
+    fn written_reservations_count_once_after_a_refresh() {
+        let available_memory = AVAILABLE_MEMORY_THRESHOLD + 1000;
+        let ledger = ledger(MemoryCap::Bytes(u64::MAX), u64::MAX, available_memory);
+
+        let mut first = ledger.reserve(600).unwrap();
+        first.set_resident(400);
+        // not refreshed yet, so the system doesn't know about the written bytes
+        assert!(ledger.reserve(600).is_err());
+
+        // the refresh sees 400 bytes less, which are no longer counted twice
+        ledger.record(u64::MAX, available_memory - 400);
+        assert!(ledger.reserve(401).is_err());
+        let _second = ledger.reserve(400).unwrap();
+
+        // given back, but only available again after the next refresh
+        drop(first);
+        assert!(ledger.reserve(201).is_err());
+        ledger.record(u64::MAX, available_memory);
+        let _third = ledger.reserve(600).unwrap();
+    }
+
+    #[test]
+    fn shrinking_gives_memory_back() {
+        let ledger = ledger(MemoryCap::Bytes(1000), u64::MAX, u64::MAX);
+
+        let mut reservation = ledger.reserve(800).unwrap();
+        reservation.shrink_to(300);
+        assert_eq!(ledger.reserved(), 300);
+        reservation.shrink_to(500);
+        assert_eq!(ledger.reserved(), 300);
+        drop(reservation);
+        assert_eq!(ledger.reserved(), 0);
+    }
+}
@@ -0,0 +1,267 @@
+use bytes::Bytes;
+use serde::Serialize;
+
+use crate::codec;
+use crate::error::{Error, ErrorCode};
+use crate::matrix_type::MatrixType;
+
+/// What a job does with its matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Operation {
+    Transpose,
+    // mirrors the matrix along the anti-diagonal
+    AntiTranspose,
+    // clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // mirrors every row
+    FlipHorizontal,
+    // mirrors every column
+    FlipVertical,
+    Negate,
+    Abs,
+    // the job's matrix is the left operand, the right one is uploaded after it
+    Multiply(Product),
+    Reduce(Reduction),
+}
+
+/// The right operand of a product, whose rows are the columns of the left one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Product {
+    pub columns: u32,
+    pub arithmetic: Arithmetic,
+}
+
+/// Boils a matrix down to a few numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Reduction {
+    // the sum of the main diagonal, which ends at the last row or column
+    Trace,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    // a column with the sum of every row
+    RowSums,
+    // a row with the sum of every column
+    ColumnSums,
+}
+
+/// Converts the elements of a job's result to another type.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Conversion {
+    pub matrix_type: MatrixType,
+    pub arithmetic: Arithmetic,
+}
+
+/// What integer elements do when a product, a sum or a converted value doesn't
+/// fit into them. Floats always follow IEEE 754.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Arithmetic {
+    Wrapping,
+    Saturating,
+}
+
+impl Operation {
+    pub const MULTIPLY: u8 = 9;
+
+    // the operations on a single matrix
+    pub const UNARY: [Operation; 9] = [
+        Operation::Transpose,
+        Operation::AntiTranspose,
+        Operation::Rotate90,
+        Operation::Rotate180,
+        Operation::Rotate270,
+        Operation::FlipHorizontal,
+        Operation::FlipVertical,
+        Operation::Negate,
+        Operation::Abs,
+    ];
+
+    /// The type of the result's elements.
+    pub fn output_type(&self, matrix_type: MatrixType) -> MatrixType {
+        match self {
+            Operation::Reduce(reduction) => reduction.output_type(matrix_type),
+            _ => matrix_type,
+        }
+    }
+
+    /// The number of rows and columns of the result.
+    pub fn output_shape(&self, rows: u32, columns: u32) -> (u32, u32) {
+        match self {
+            Operation::Transpose
+            | Operation::AntiTranspose
+            | Operation::Rotate90
+            | Operation::Rotate270 => (columns, rows),
+            Operation::Rotate180
+            | Operation::FlipHorizontal
+            | Operation::FlipVertical
+            | Operation::Negate
+            | Operation::Abs => (rows, columns),
+            Operation::Multiply(product) => (rows, product.columns),
+            Operation::Reduce(Reduction::RowSums) => (rows, 1),
+            Operation::Reduce(Reduction::ColumnSums) => (1, columns),
+            Operation::Reduce(_) => (1, 1),
+        }
+    }
+
+    /// Reads an operation code and, for a product, the columns of the right
+    /// operand followed by the arithmetic, which may be left out.
+    pub fn read(payload: &mut Bytes) -> Result<Operation, Error> {
+        let operation = match codec::read_u8(payload)? {
+            0 => Operation::Transpose,
+            1 => Operation::AntiTranspose,
+            2 => Operation::Rotate90,
+            3 => Operation::Rotate180,
+            4 => Operation::Rotate270,
+            5 => Operation::FlipHorizontal,
+            6 => Operation::FlipVertical,
+            7 => Operation::Negate,
+            8 => Operation::Abs,
+            Operation::MULTIPLY => Operation::Multiply(Product {
+                columns: codec::read_u32(payload)?,
+                arithmetic: match payload.is_empty() {
+                    true => Arithmetic::Wrapping,
+                    false => Arithmetic::try_from(codec::read_u8(payload)?)?,
+                },
+            }),
+            10 => Operation::Reduce(Reduction::Trace),
+            11 => Operation::Reduce(Reduction::Sum),
+            12 => Operation::Reduce(Reduction::Min),
+            13 => Operation::Reduce(Reduction::Max),
+            14 => Operation::Reduce(Reduction::Mean),
+            15 => Operation::Reduce(Reduction::RowSums),
+            16 => Operation::Reduce(Reduction::ColumnSums),
+            code => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid operation code: {}", code),
+            ))?,
+        };
+        Ok(operation)
+    }
+}
+
+impl std::convert::From<Operation> for u8 {
+    fn from(value: Operation) -> Self {
+        match value {
+            Operation::Transpose => 0,
+            Operation::AntiTranspose => 1,
+            Operation::Rotate90 => 2,
+            Operation::Rotate180 => 3,
+            Operation::Rotate270 => 4,
+            Operation::FlipHorizontal => 5,
+            Operation::FlipVertical => 6,
+            Operation::Negate => 7,
+            Operation::Abs => 8,
+            Operation::Multiply(_) => Operation::MULTIPLY,
+            Operation::Reduce(Reduction::Trace) => 10,
+            Operation::Reduce(Reduction::Sum) => 11,
+            Operation::Reduce(Reduction::Min) => 12,
+            Operation::Reduce(Reduction::Max) => 13,
+            Operation::Reduce(Reduction::Mean) => 14,
+            Operation::Reduce(Reduction::RowSums) => 15,
+            Operation::Reduce(Reduction::ColumnSums) => 16,
+        }
+    }
+}
+
+impl std::convert::From<Operation> for String {
+    fn from(value: Operation) -> Self {
+        match value {
+            Operation::Transpose => String::from("transpose"),
+            Operation::AntiTranspose => String::from("anti-transpose"),
+            Operation::Rotate90 => String::from("rotate 90"),
+            Operation::Rotate180 => String::from("rotate 180"),
+            Operation::Rotate270 => String::from("rotate 270"),
+            Operation::FlipHorizontal => String::from("flip horizontal"),
+            Operation::FlipVertical => String::from("flip vertical"),
+            Operation::Negate => String::from("negate"),
+            Operation::Abs => String::from("abs"),
+            Operation::Multiply(_) => String::from("multiply"),
+            Operation::Reduce(Reduction::Trace) => String::from("trace"),
+            Operation::Reduce(Reduction::Sum) => String::from("sum"),
+            Operation::Reduce(Reduction::Min) => String::from("min"),
+            Operation::Reduce(Reduction::Max) => String::from("max"),
+            Operation::Reduce(Reduction::Mean) => String::from("mean"),
+            Operation::Reduce(Reduction::RowSums) => String::from("row sums"),
+            Operation::Reduce(Reduction::ColumnSums) => String::from("column sums"),
+        }
+    }
+}
+
+impl Reduction {
+    pub const ALL: [Reduction; 7] = [
+        Reduction::Trace,
+        Reduction::Sum,
+        Reduction::Min,
+        Reduction::Max,
+        Reduction::Mean,
+        Reduction::RowSums,
+        Reduction::ColumnSums,
+    ];
+
+    // sums are 64 bits wide and keep the signedness of the elements, the mean
+    // is always a float, and min and max are elements themselves
+    fn output_type(&self, matrix_type: MatrixType) -> MatrixType {
+        match self {
+            Reduction::Min | Reduction::Max => matrix_type,
+            Reduction::Mean => MatrixType::F64,
+            Reduction::Trace | Reduction::Sum | Reduction::RowSums | Reduction::ColumnSums => {
+                match matrix_type {
+                    MatrixType::U8 | MatrixType::U16 | MatrixType::U32 | MatrixType::U64 => {
+                        MatrixType::U64
+                    }
+                    MatrixType::I8 | MatrixType::I16 | MatrixType::I32 | MatrixType::I64 => {
+                        MatrixType::I64
+                    }
+                    MatrixType::F32 | MatrixType::F64 => MatrixType::F64,
+                }
+            }
+        }
+    }
+}
+
+impl Conversion {
+    /// Reads an output type code followed by the arithmetic, which may be left
+    /// out. Without the output type there is nothing to convert.
+    pub fn read(payload: &mut Bytes) -> Result<Option<Conversion>, Error> {
+        if payload.is_empty() {
+            return Ok(None);
+        }
+
+        let conversion = Conversion {
+            matrix_type: MatrixType::try_from(codec::read_u8(payload)?)?,
+            // converted values are clamped unless asked otherwise
+            arithmetic: match payload.is_empty() {
+                true => Arithmetic::Saturating,
+                false => Arithmetic::try_from(codec::read_u8(payload)?)?,
+            },
+        };
+        Ok(Some(conversion))
+    }
+}
+
+impl std::convert::TryFrom<u8> for Arithmetic {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Arithmetic::Wrapping),
+            1 => Ok(Arithmetic::Saturating),
+            _ => Err(Error::new(
+                ErrorCode::InvalidArgument,
+                format!("Invalid arithmetic code: {}", value),
+            )),
+        }
+    }
+}
+
+impl std::convert::From<Arithmetic> for u8 {
+    fn from(value: Arithmetic) -> Self {
+        match value {
+            Arithmetic::Wrapping => 0,
+            Arithmetic::Saturating => 1,
+        }
+    }
+}
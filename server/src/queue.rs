@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{Error, ErrorCode};
+
+// the retry hint before any job has been transposed, and the least one ever given
+const MIN_RETRY_AFTER_MS: u64 = 100;
+
+/// Counts the uploaded matrices waiting for the thread pool, so that a busy
+/// server turns new jobs away instead of queueing them without a limit.
+pub struct JobQueue {
+    max_depth: usize,
+    depth: AtomicUsize,
+    // a moving average of how long a transposition takes
+    average_job_ms: AtomicU64,
+}
+
+impl JobQueue {
+    pub fn new(max_depth: usize) -> JobQueue {
+        JobQueue {
+            max_depth,
+            depth: AtomicUsize::new(0),
+            average_job_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes a place in the queue, which is given back when the returned slot
+    /// is dropped. A full queue tells the client when to try again.
+    pub fn enter(self: &Arc<Self>) -> Result<QueueSlot, Error> {
+        let entered = self
+            .depth
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |depth| {
+                (depth < self.max_depth).then_some(depth + 1)
+            });
+        match entered {
+            Ok(_) => Ok(QueueSlot {
+                queue: Arc::clone(self),
+            }),
+            Err(depth) => Err(Error::new(
+                ErrorCode::ServerBusy,
+                format!("{depth} jobs are already waiting for the thread pool"),
+            )
+            .with_retry_after(self.retry_after())),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Acquire)
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let ms = duration.as_millis().min(u64::MAX as u128) as u64;
+        let _ = self
+            .average_job_ms
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => ms,
+                    average => average.saturating_mul(7).saturating_add(ms) / 8,
+                })
+            });
+    }
+
+    // a place in the queue frees up as soon as a job finishes
+    fn retry_after(&self) -> Duration {
+        let ms = self.average_job_ms.load(Ordering::Relaxed);
+        Duration::from_millis(ms.max(MIN_RETRY_AFTER_MS))
+    }
+}
+
+pub struct QueueSlot {
+    queue: Arc<JobQueue>,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.queue.depth.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_queue_turns_jobs_away() {
+        let queue = Arc::new(JobQueue::new(2));
+
+        let first = queue.enter().unwrap();
+        let _second = queue.enter().unwrap();
+        let error = queue.enter().err().unwrap();
+        assert_eq!(error.code, ErrorCode::ServerBusy);
+        assert_eq!(
+            error.retry_after,
+            Some(Duration::from_millis(MIN_RETRY_AFTER_MS))
+        );
+        assert_eq!(queue.depth(), 2);
+
+        drop(first);
+        assert_eq!(queue.depth(), 1);
+        let _third = queue.enter().unwrap();
+    }
+
+    #[test]
+    fn retry_after_follows_the_job_durations() {
+        let queue = Arc::new(JobQueue::new(0));
+
+        queue.record(Duration::from_millis(800));
+        assert_eq!(queue.retry_after(), Duration::from_millis(800));
+        queue.record(Duration::from_millis(0));
+        assert_eq!(queue.retry_after(), Duration::from_millis(700));
+        let error = queue.enter().err().unwrap();
+        assert_eq!(error.retry_after, Some(Duration::from_millis(700)));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, ErrorCode};
+
+/// Whose jobs count against a quota. Every connection is a client of its own,
+/// numbered by the server, since anyone can give any name in the hello.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ClientId {
+    connection: u64,
+    // only shown in the messages about the client
+    name: Option<String>,
+}
+
+impl ClientId {
+    pub fn new(name: Option<String>) -> ClientId {
+        static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+        ClientId {
+            connection: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+            name,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} (connection #{})", self.connection),
+            None => write!(f, "connection #{}", self.connection),
+        }
+    }
+}
+
+/// The most a single client may hold at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    // the jobs that hold memory: reserved, running or completed
+    pub max_jobs: u32,
+    // the bytes of the matrices of those jobs
+    pub max_bytes: u64,
+    pub max_running: u32,
+}
+
+impl Quota {
+    pub const UNLIMITED: Quota = Quota {
+        max_jobs: u32::MAX,
+        max_bytes: u64::MAX,
+        max_running: u32::MAX,
+    };
+}
+
+/// What a client holds right now, and its quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub jobs: u32,
+    pub max_jobs: u32,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub running: u32,
+    pub max_running: u32,
+}
+
+#[derive(Default)]
+struct ClientState {
+    jobs: u32,
+    bytes: u64,
+    running: u32,
+}
+
+/// Keeps count of what every client holds, so that one client can't take the
+/// whole server for itself.
+pub struct QuotaLedger {
+    quota: Quota,
+    clients: Mutex<HashMap<ClientId, ClientState>>,
+}
+
+fn quota_exceeded(message: String) -> Error {
+    Error::new(ErrorCode::QuotaExceeded, message)
+}
+
+impl QuotaLedger {
+    pub fn new(quota: Quota) -> QuotaLedger {
+        QuotaLedger {
+            quota,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new job of `bytes` against the client's quota until the
+    /// returned reservation is dropped.
+    pub fn reserve(self: &Arc<Self>, client: &ClientId, bytes: u64) -> Result<JobQuota, Error> {
+        let mut clients = self.clients.lock().unwrap();
+        let usage = clients.get(client);
+        let jobs = usage.map_or(0, |state| state.jobs);
+        let held_bytes = usage.map_or(0, |state| state.bytes);
+
+        if jobs >= self.quota.max_jobs {
+            return Err(quota_exceeded(format!(
+                "{client} already holds {jobs} of {} jobs",
+                self.quota.max_jobs
+            )));
+        }
+        if held_bytes.saturating_add(bytes) > self.quota.max_bytes {
+            return Err(quota_exceeded(format!(
+                "{client} needs {bytes} bytes, {held_bytes} of {} bytes are already held",
+                self.quota.max_bytes
+            )));
+        }
+
+        let state = clients.entry(client.clone()).or_default();
+        state.jobs += 1;
+        state.bytes += bytes;
+        Ok(JobQuota {
+            ledger: Arc::clone(self),
+            client: client.clone(),
+            bytes,
+            running: false,
+        })
+    }
+
+    pub fn usage(&self, client: &ClientId) -> Usage {
+        let clients = self.clients.lock().unwrap();
+        let state = clients.get(client);
+        Usage {
+            jobs: state.map_or(0, |state| state.jobs),
+            max_jobs: self.quota.max_jobs,
+            bytes: state.map_or(0, |state| state.bytes),
+            max_bytes: self.quota.max_bytes,
+            running: state.map_or(0, |state| state.running),
+            max_running: self.quota.max_running,
+        }
+    }
+
+    fn update(&self, client: &ClientId, update: impl FnOnce(&mut ClientState)) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(state) = clients.get_mut(client) {
+            update(state);
+            // a client that holds nothing is forgotten
+            if state.jobs == 0 {
+                clients.remove(client);
+            }
+        }
+    }
+}
+
+/// A job's share of its client's quota.
+pub struct JobQuota {
+    ledger: Arc<QuotaLedger>,
+    client: ClientId,
+    bytes: u64,
+    running: bool,
+}
+
+impl JobQuota {
+    // counts the job as running, if the client may run one more
+    pub fn start(&mut self) -> Result<(), Error> {
+        let mut clients = self.ledger.clients.lock().unwrap();
+        let state = clients.entry(self.client.clone()).or_default();
+        if state.running >= self.ledger.quota.max_running {
+            return Err(quota_exceeded(format!(
+                "{} already runs {} of {} jobs",
+                self.client, state.running, self.ledger.quota.max_running
+            )));
+        }
+        state.running += 1;
+        self.running = true;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) {
+        if self.running {
+            self.ledger.update(&self.client, |state| state.running -= 1);
+            self.running = false;
+        }
+    }
+
+    // counts the job against another client's quota, even if that takes the
+    // client over it, since the job is already held
+    pub fn move_to(&mut self, client: &ClientId) {
+        if self.client == *client {
+            return;
+        }
+
+        let (bytes, running) = (self.bytes, self.running as u32);
+        self.ledger.update(&self.client, |state| {
+            state.jobs -= 1;
+            state.bytes -= bytes;
+            state.running -= running;
+        });
+        let mut clients = self.ledger.clients.lock().unwrap();
+        let state = clients.entry(client.clone()).or_default();
+        state.jobs += 1;
+        state.bytes += bytes;
+        state.running += running;
+        self.client = client.clone();
+    }
+}
+
+impl Drop for JobQuota {
+    fn drop(&mut self) {
+        let (bytes, running) = (self.bytes, self.running as u32);
+        self.ledger.update(&self.client, |state| {
+            state.jobs -= 1;
+            state.bytes -= bytes;
+            state.running -= running;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(max_jobs: u32, max_bytes: u64, max_running: u32) -> Arc<QuotaLedger> {
+        Arc::new(QuotaLedger::new(Quota {
+            max_jobs,
+            max_bytes,
+            max_running,
+        }))
+    }
+
+    #[test]
+    fn limits_the_jobs_and_bytes_of_each_client() {
+        let ledger = ledger(2, 100, u32::MAX);
+        let alice = ClientId::new(Some(String::from("alice")));
+        let bob = ClientId::new(None);
+        // a name doesn't give a connection the quota of another one
+        let impostor = ClientId::new(Some(String::from("alice")));
+
+        let first = ledger.reserve(&alice, 60).unwrap();
+        let error = ledger.reserve(&alice, 41).err().unwrap();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        let _second = ledger.reserve(&alice, 40).unwrap();
+        let error = ledger.reserve(&alice, 0).err().unwrap();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        // other clients have quotas of their own
+        let _third = ledger.reserve(&bob, 100).unwrap();
+        let _impostors = ledger.reserve(&impostor, 100).unwrap();
+
+        drop(first);
+        let usage = ledger.usage(&alice);
+        assert_eq!((usage.jobs, usage.bytes), (1, 40));
+        let _fourth = ledger.reserve(&alice, 60).unwrap();
+    }
+
+    #[test]
+    fn jobs_move_to_another_client() {
+        let ledger = ledger(1, 100, 1);
+        let old = ClientId::new(Some(String::from("client")));
+        let new = ClientId::new(Some(String::from("client")));
+
+        let mut quota = ledger.reserve(&old, 60).unwrap();
+        quota.start().unwrap();
+        quota.move_to(&new);
+        assert_eq!(ledger.usage(&old), ledger.usage(&ClientId::new(None)));
+        let usage = ledger.usage(&new);
+        assert_eq!((usage.jobs, usage.bytes, usage.running), (1, 60, 1));
+        // the new client is at its quota now, the old one is free again
+        assert!(ledger.reserve(&new, 1).is_err());
+        let _other = ledger.reserve(&old, 100).unwrap();
+
+        drop(quota);
+        assert_eq!(ledger.usage(&new), ledger.usage(&ClientId::new(None)));
+    }
+
+    #[test]
+    fn limits_the_running_jobs_of_each_client() {
+        let ledger = ledger(u32::MAX, u64::MAX, 1);
+        let client = ClientId::new(None);
+
+        let mut first = ledger.reserve(&client, 1).unwrap();
+        let mut second = ledger.reserve(&client, 1).unwrap();
+        first.start().unwrap();
+        let error = second.start().err().unwrap();
+        assert_eq!(error.code, ErrorCode::QuotaExceeded);
+        assert_eq!(ledger.usage(&client).running, 1);
+
+        first.finish();
+        second.start().unwrap();
+        drop(second);
+        assert_eq!(ledger.usage(&client).running, 0);
+        assert_eq!(ledger.usage(&client).jobs, 1);
+
+        drop(first);
+        assert_eq!(
+            ledger.usage(&client),
+            Usage {
+                max_jobs: u32::MAX,
+                max_bytes: u64::MAX,
+                max_running: 1,
+                ..Usage::default()
+            }
+        );
+        assert!(ledger.clients.lock().unwrap().is_empty());
+    }
+}
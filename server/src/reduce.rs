@@ -0,0 +1,409 @@
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    matrix_type::{with_element, Element, MatrixType},
+    operation::Reduction,
+};
+
+// elements per task, enough to outweigh the cost of scheduling it. The chunks
+// are always the same, so float sums don't depend on the number of threads
+const CHUNK_ELEMENTS: usize = 1 << 14;
+// the columns a single worker sums up
+const BAND_COLUMNS: usize = 1 << 10;
+
+trait Reduce: Element {
+    // what the elements are summed in: exactly for integers, in f64 for floats
+    type Total: Copy + Default + Send + Sync + std::ops::Add<Output = Self::Total>;
+    // the elements of a sum, see Reduction::output_type
+    type Sum: Element;
+
+    fn total(self) -> Self::Total;
+    fn sum(total: Self::Total) -> Self::Sum;
+    fn mean(total: Self::Total, count: usize) -> f64;
+    // NaNs are skipped unless there's nothing else
+    fn min(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+}
+
+macro_rules! integer {
+    ($($element:ty => $total:ty, $sum:ty;)*) => {$(
+        impl Reduce for $element {
+            type Total = $total;
+            type Sum = $sum;
+
+            fn total(self) -> $total {
+                self as $total
+            }
+
+            // cutting the exact sum down to 64 bits is the same as wrapping around
+            fn sum(total: $total) -> $sum {
+                total as $sum
+            }
+
+            fn mean(total: $total, count: usize) -> f64 {
+                total as f64 / count as f64
+            }
+
+            fn min(self, other: Self) -> Self {
+                Ord::min(self, other)
+            }
+
+            fn max(self, other: Self) -> Self {
+                Ord::max(self, other)
+            }
+        }
+    )*};
+}
+
+macro_rules! float {
+    ($($element:ty),*) => {$(
+        impl Reduce for $element {
+            type Total = f64;
+            type Sum = f64;
+
+            fn total(self) -> f64 {
+                self as f64
+            }
+
+            fn sum(total: f64) -> f64 {
+                total
+            }
+
+            fn mean(total: f64, count: usize) -> f64 {
+                total / count as f64
+            }
+
+            fn min(self, other: Self) -> Self {
+                <$element>::min(self, other)
+            }
+
+            fn max(self, other: Self) -> Self {
+                <$element>::max(self, other)
+            }
+        }
+    )*};
+}
+
+// even 2^31 of the largest 64-bit elements don't overflow 128 bits
+integer! {
+    u8 => u128, u64;
+    u16 => u128, u64;
+    u32 => u128, u64;
+    u64 => u128, u64;
+    i8 => i128, i64;
+    i16 => i128, i64;
+    i32 => i128, i64;
+    i64 => i128, i64;
+}
+float!(f32, f64);
+
+/// Reduces a `rows`x`columns` matrix, which can't be empty. Must be called from
+/// inside the thread pool. A cancelled reduction stops early and returns an
+/// incomplete result.
+pub fn reduce(
+    reduction: Reduction,
+    matrix_type: MatrixType,
+    matrix_vec: &[u8],
+    rows: usize,
+    columns: usize,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    with_element!(matrix_type, T => {
+        reduce_typed::<T>(reduction, matrix_vec, rows, columns, cancelled)
+    })
+}
+
+fn reduce_typed<T: Reduce>(
+    reduction: Reduction,
+    matrix_vec: &[u8],
+    rows: usize,
+    columns: usize,
+    cancelled: &AtomicBool,
+) -> Vec<u8> {
+    match reduction {
+        Reduction::Trace => {
+            let total = (0..rows.min(columns))
+                .map(|i| T::read(&matrix_vec[(i * columns + i) * T::SIZE..]).total())
+                .fold(T::Total::default(), |total, element| total + element);
+            to_bytes(&[T::sum(total)])
+        }
+        Reduction::Sum => to_bytes(&[T::sum(total::<T>(matrix_vec, cancelled))]),
+        Reduction::Mean => {
+            let total = total::<T>(matrix_vec, cancelled);
+            to_bytes(&[T::mean(total, rows * columns)])
+        }
+        Reduction::Min => to_bytes(&[extreme(matrix_vec, cancelled, T::min)]),
+        Reduction::Max => to_bytes(&[extreme(matrix_vec, cancelled, T::max)]),
+        Reduction::RowSums => to_bytes(&row_sums::<T>(matrix_vec, columns, cancelled)),
+        Reduction::ColumnSums => to_bytes(&column_sums::<T>(matrix_vec, rows, columns, cancelled)),
+    }
+}
+
+fn to_bytes<E: Element>(elements: &[E]) -> Vec<u8> {
+    let mut bytes = vec![0u8; elements.len() * E::SIZE];
+    for (element, dst) in elements.iter().zip(bytes.chunks_exact_mut(E::SIZE)) {
+        element.write(dst);
+    }
+    bytes
+}
+
+// the chunks are summed in parallel, and their totals one after another
+fn total<T: Reduce>(matrix_vec: &[u8], cancelled: &AtomicBool) -> T::Total {
+    let totals: Vec<T::Total> = matrix_vec
+        .par_chunks(CHUNK_ELEMENTS * T::SIZE)
+        .map(|chunk| match cancelled.load(Ordering::Relaxed) {
+            true => T::Total::default(),
+            false => chunk
+                .chunks_exact(T::SIZE)
+                .fold(T::Total::default(), |total, element| {
+                    total + T::read(element).total()
+                }),
+        })
+        .collect();
+    totals
+        .into_iter()
+        .fold(T::Total::default(), |total, chunk_total| {
+            total + chunk_total
+        })
+}
+
+fn extreme<T: Reduce>(matrix_vec: &[u8], cancelled: &AtomicBool, pick: fn(T, T) -> T) -> T {
+    let extremes: Vec<T> = matrix_vec
+        .par_chunks(CHUNK_ELEMENTS * T::SIZE)
+        .map(|chunk| {
+            let mut elements = chunk.chunks_exact(T::SIZE).map(T::read);
+            let first = elements.next().unwrap();
+            match cancelled.load(Ordering::Relaxed) {
+                true => first,
+                false => elements.fold(first, pick),
+            }
+        })
+        .collect();
+    extremes.into_iter().reduce(pick).unwrap()
+}
+
+fn row_sums<T: Reduce>(matrix_vec: &[u8], columns: usize, cancelled: &AtomicBool) -> Vec<T::Sum> {
+    matrix_vec
+        .par_chunks(columns * T::SIZE)
+        .map(|row| match cancelled.load(Ordering::Relaxed) {
+            true => T::Sum::default(),
+            false => T::sum(
+                row.chunks_exact(T::SIZE)
+                    .fold(T::Total::default(), |total, element| {
+                        total + T::read(element).total()
+                    }),
+            ),
+        })
+        .collect()
+}
+
+// every worker owns a band of columns and walks down the whole matrix, so every
+// column is summed from the first row to the last
+fn column_sums<T: Reduce>(
+    matrix_vec: &[u8],
+    rows: usize,
+    columns: usize,
+    cancelled: &AtomicBool,
+) -> Vec<T::Sum> {
+    let mut sums = vec![T::Sum::default(); columns];
+    sums.par_chunks_mut(BAND_COLUMNS)
+        .enumerate()
+        .for_each(|(band, band_sums)| {
+            let first_column = band * BAND_COLUMNS;
+            let mut totals = vec![T::Total::default(); band_sums.len()];
+            for i in 0..rows {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                let row = &matrix_vec[(i * columns + first_column) * T::SIZE..];
+                for (total, element) in totals.iter_mut().zip(row.chunks_exact(T::SIZE)) {
+                    *total = *total + T::read(element).total();
+                }
+            }
+            for (sum, total) in band_sums.iter_mut().zip(totals) {
+                *sum = T::sum(total);
+            }
+        });
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Operation;
+
+    fn reduce_elementwise<T: Reduce>(
+        reduction: Reduction,
+        elements: &[T],
+        rows: usize,
+        columns: usize,
+    ) -> Vec<u8> {
+        let element = |i: usize, j: usize| elements[i * columns + j];
+        let sum = |elements: &mut dyn Iterator<Item = T>| {
+            T::sum(elements.fold(T::Total::default(), |total, element| {
+                total + element.total()
+            }))
+        };
+        match reduction {
+            Reduction::Trace => {
+                to_bytes(&[sum(&mut (0..rows.min(columns)).map(|i| element(i, i)))])
+            }
+            Reduction::Sum => to_bytes(&[sum(&mut elements.iter().copied())]),
+            Reduction::Mean => {
+                let total = elements.iter().fold(T::Total::default(), |total, element| {
+                    total + element.total()
+                });
+                to_bytes(&[T::mean(total, elements.len())])
+            }
+            Reduction::Min => to_bytes(&[elements.iter().copied().reduce(T::min).unwrap()]),
+            Reduction::Max => to_bytes(&[elements.iter().copied().reduce(T::max).unwrap()]),
+            Reduction::RowSums => to_bytes(
+                &(0..rows)
+                    .map(|i| sum(&mut (0..columns).map(|j| element(i, j))))
+                    .collect::<Vec<_>>(),
+            ),
+            Reduction::ColumnSums => to_bytes(
+                &(0..columns)
+                    .map(|j| sum(&mut (0..rows).map(|i| element(i, j))))
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+
+    // floats are whole numbers, so that their sums are exact in any order
+    fn against_elementwise<T: Reduce>(matrix_type: MatrixType, random: fn() -> T) {
+        let cancelled = AtomicBool::new(false);
+        // the larger ones span several chunks and bands
+        for (rows, columns) in [(1, 1), (3, 5), (5, 3), (20, 1100), (9000, 2)] {
+            let elements: Vec<T> = (0..rows * columns).map(|_| random()).collect();
+            let matrix_vec = to_bytes(&elements);
+
+            for reduction in Reduction::ALL {
+                let operation = Operation::Reduce(reduction);
+                let result_vec = reduce(
+                    reduction,
+                    matrix_type,
+                    &matrix_vec,
+                    rows,
+                    columns,
+                    &cancelled,
+                );
+                let (result_rows, result_columns) =
+                    operation.output_shape(rows as u32, columns as u32);
+                let result_type = operation.output_type(matrix_type);
+                assert_eq!(
+                    result_vec.len(),
+                    (result_rows * result_columns) as usize * result_type.get_type_size() as usize
+                );
+                assert_eq!(
+                    result_vec,
+                    reduce_elementwise(reduction, &elements, rows, columns),
+                    "results differ for the {} of a {rows}x{columns} {} matrix",
+                    String::from(operation),
+                    String::from(matrix_type)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_against_elementwise() {
+        against_elementwise::<u8>(MatrixType::U8, rand::random);
+        against_elementwise::<u16>(MatrixType::U16, rand::random);
+        against_elementwise::<u32>(MatrixType::U32, rand::random);
+        against_elementwise::<u64>(MatrixType::U64, rand::random);
+        against_elementwise::<i8>(MatrixType::I8, rand::random);
+        against_elementwise::<i16>(MatrixType::I16, rand::random);
+        against_elementwise::<i32>(MatrixType::I32, rand::random);
+        against_elementwise::<i64>(MatrixType::I64, rand::random);
+        against_elementwise::<f32>(MatrixType::F32, || rand::random::<i16>() as f32);
+        against_elementwise::<f64>(MatrixType::F64, || rand::random::<i32>() as f64);
+    }
+
+    fn reduce_f32(reduction: Reduction, elements: &[f32], rows: usize, columns: usize) -> Vec<f64> {
+        let cancelled = AtomicBool::new(false);
+        let result_vec = reduce(
+            reduction,
+            MatrixType::F32,
+            &to_bytes(elements),
+            rows,
+            columns,
+            &cancelled,
+        );
+        match reduction {
+            // min and max keep the type of the elements
+            Reduction::Min | Reduction::Max => result_vec
+                .chunks_exact(4)
+                .map(|bytes| f32::read(bytes) as f64)
+                .collect(),
+            _ => result_vec.chunks_exact(8).map(f64::read).collect(),
+        }
+    }
+
+    #[test]
+    fn nan_handling() {
+        let elements = [1.0, f32::NAN, -2.0, 3.0];
+        let nan = |result: Vec<f64>| result.iter().map(|x| x.is_nan()).collect::<Vec<_>>();
+
+        // sums and the mean are NaN as soon as a single element is
+        assert_eq!(nan(reduce_f32(Reduction::Sum, &elements, 2, 2)), [true]);
+        assert_eq!(nan(reduce_f32(Reduction::Mean, &elements, 2, 2)), [true]);
+        assert_eq!(reduce_f32(Reduction::Trace, &elements, 2, 2), [4.0]);
+        assert_eq!(
+            nan(reduce_f32(Reduction::RowSums, &elements, 2, 2)),
+            [true, false]
+        );
+        assert_eq!(reduce_f32(Reduction::RowSums, &elements, 2, 2)[1], 1.0);
+        assert_eq!(
+            nan(reduce_f32(Reduction::ColumnSums, &elements, 2, 2)),
+            [false, true]
+        );
+        assert_eq!(reduce_f32(Reduction::ColumnSums, &elements, 2, 2)[0], -1.0);
+        // min and max skip NaNs, unless every element is one
+        assert_eq!(reduce_f32(Reduction::Min, &elements, 2, 2), [-2.0]);
+        assert_eq!(reduce_f32(Reduction::Max, &elements, 2, 2), [3.0]);
+        assert_eq!(
+            nan(reduce_f32(Reduction::Max, &[f32::NAN; 3], 1, 3)),
+            [true]
+        );
+    }
+
+    #[test]
+    fn integer_sums_wrap_around() {
+        let cancelled = AtomicBool::new(false);
+        let matrix_vec = to_bytes(&[u64::MAX, 1, 2]);
+
+        let sum = reduce(
+            Reduction::Sum,
+            MatrixType::U64,
+            &matrix_vec,
+            1,
+            3,
+            &cancelled,
+        );
+        assert_eq!(sum, 2u64.to_le_bytes());
+        // the mean is taken of the exact sum
+        let mean = reduce(
+            Reduction::Mean,
+            MatrixType::U64,
+            &matrix_vec,
+            1,
+            3,
+            &cancelled,
+        );
+        assert_eq!(mean, ((u64::MAX as f64 + 3.0) / 3.0).to_le_bytes());
+
+        // smaller elements are summed in 64 bits
+        let matrix_vec = to_bytes(&[i8::MIN, i8::MIN]);
+        let sum = reduce(
+            Reduction::Sum,
+            MatrixType::I8,
+            &matrix_vec,
+            2,
+            1,
+            &cancelled,
+        );
+        assert_eq!(sum, (-256i64).to_le_bytes());
+    }
+}
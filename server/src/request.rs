@@ -1,23 +1,70 @@
+use bytes::Bytes;
+#[cfg(test)]
+use bytes::{BufMut, BytesMut};
 use serde::Serialize;
-use std::error::Error;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::job::JobManager;
-use crate::{matrix_type::MatrixType, response::Response};
+use crate::codec;
+use crate::error::{Error, ErrorCode};
+use crate::job::{FetchUnit, JobManager};
+use crate::quota::ClientId;
+use crate::token::Token;
+use crate::{
+    matrix_type::MatrixType,
+    operation::{Conversion, Operation},
+    response::Response,
+};
 
-#[derive(Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum Request {
     Reserve {
         matrix_type: MatrixType,
-        matrix_dimension: u32,
+        matrix_rows: u32,
+        matrix_columns: u32,
+        // a job with a higher priority is started first
+        priority: u8,
+        operation: Operation,
+        // the elements of the result are converted to another type
+        conversion: Option<Conversion>,
     },
     Calc {
-        id: usize,
+        id: Token,
+        #[serde(skip)]
+        matrix: Bytes,
     },
     Poll {
-        id: usize,
+        id: Token,
+    },
+    Cancel {
+        id: Token,
+    },
+    Upload {
+        id: Token,
+        offset: u64,
+        #[serde(skip)]
+        chunk: Bytes,
+    },
+    Resume {
+        id: Token,
+    },
+    Fetch {
+        id: Token,
+        unit: FetchUnit,
+        start: u64,
+        count: u64,
+    },
+    Wait {
+        id: Token,
+        timeout_ms: u32,
+    },
+    Hello {
+        protocol_version: u16,
+        // only shown in the log, the quotas apply to the connection whatever its name
+        client_name: Option<String>,
+    },
+    Usage,
+    Release {
+        id: Token,
     },
 }
 
@@ -27,82 +74,265 @@ impl std::convert::From<&Request> for String {
             Request::Reserve { .. } => String::from("reserve"),
             Request::Calc { .. } => String::from("calc"),
             Request::Poll { .. } => String::from("poll"),
+            Request::Cancel { .. } => String::from("cancel"),
+            Request::Upload { .. } => String::from("upload"),
+            Request::Resume { .. } => String::from("resume"),
+            Request::Fetch { .. } => String::from("fetch"),
+            Request::Wait { .. } => String::from("wait"),
+            Request::Hello { .. } => String::from("hello"),
+            Request::Usage => String::from("usage"),
+            Request::Release { .. } => String::from("release"),
         }
     }
 }
 
 impl Request {
-    pub async fn from_stream(stream: &mut TcpStream) -> Result<Request, Box<dyn Error>> {
-        let request_code = {
-            let mut buffer = [0u8; 1];
-            stream.read_exact(&mut buffer).await?;
-            buffer[0]
+    /// Parses the payload of a request frame.
+    pub fn decode(code: u8, mut payload: Bytes) -> Result<Request, Error> {
+        let request = match code {
+            0 => Request::Reserve {
+                matrix_type: MatrixType::try_from(codec::read_u8(&mut payload)?)?,
+                matrix_rows: codec::read_u32(&mut payload)?,
+                matrix_columns: codec::read_u32(&mut payload)?,
+                // older clients don't send a priority
+                priority: match payload.is_empty() {
+                    true => 0,
+                    false => codec::read_u8(&mut payload)?,
+                },
+                // nor an operation, they only ever transpose
+                operation: match payload.is_empty() {
+                    true => Operation::Transpose,
+                    false => Operation::read(&mut payload)?,
+                },
+                conversion: Conversion::read(&mut payload)?,
+            },
+            1 => Request::Calc {
+                id: codec::read_token(&mut payload)?,
+                matrix: std::mem::take(&mut payload),
+            },
+            2 => Request::Poll {
+                id: codec::read_token(&mut payload)?,
+            },
+            4 => Request::Cancel {
+                id: codec::read_token(&mut payload)?,
+            },
+            5 => Request::Upload {
+                id: codec::read_token(&mut payload)?,
+                offset: codec::read_u64(&mut payload)?,
+                chunk: std::mem::take(&mut payload),
+            },
+            6 => Request::Resume {
+                id: codec::read_token(&mut payload)?,
+            },
+            7 => Request::Fetch {
+                id: codec::read_token(&mut payload)?,
+                unit: FetchUnit::try_from(codec::read_u8(&mut payload)?)?,
+                start: codec::read_u64(&mut payload)?,
+                count: codec::read_u64(&mut payload)?,
+            },
+            8 => Request::Wait {
+                id: codec::read_token(&mut payload)?,
+                timeout_ms: codec::read_u32(&mut payload)?,
+            },
+            9 => Request::Hello {
+                protocol_version: codec::read_u16(&mut payload)?,
+                client_name: match std::mem::take(&mut payload) {
+                    name if name.is_empty() => None,
+                    name => Some(
+                        String::from_utf8(name.to_vec())
+                            .map_err(|e| Error::new(ErrorCode::MalformedMessage, e.to_string()))?,
+                    ),
+                },
+            },
+            10 => Request::Usage,
+            11 => Request::Release {
+                id: codec::read_token(&mut payload)?,
+            },
+            code => Err(Error::new(
+                ErrorCode::UnknownRequest,
+                format!("unknown request code: {code}"),
+            ))?,
         };
+        codec::read_end(&payload)?;
 
-        match request_code {
-            0 => {
-                let matrix_type = {
-                    let mut buffer = [0u8; 1];
-                    stream.read_exact(&mut buffer).await?;
-                    MatrixType::try_from(buffer[0])?
-                };
-
-                let matrix_dimension = {
-                    let mut buffer = [0u8; 4];
-                    stream.read_exact(&mut buffer).await?;
-                    u32::from_le_bytes(buffer)
-                };
+        Ok(request)
+    }
 
-                Ok(Request::Reserve {
-                    matrix_type,
-                    matrix_dimension,
-                })
+    // the client side of the protocol
+    #[cfg(test)]
+    pub fn encode(&self, correlation_id: u32, dst: &mut BytesMut) {
+        let code = match self {
+            Request::Reserve { .. } => 0,
+            Request::Calc { .. } => 1,
+            Request::Poll { .. } => 2,
+            Request::Cancel { .. } => 4,
+            Request::Upload { .. } => 5,
+            Request::Resume { .. } => 6,
+            Request::Fetch { .. } => 7,
+            Request::Wait { .. } => 8,
+            Request::Hello { .. } => 9,
+            Request::Usage => 10,
+            Request::Release { .. } => 11,
+        };
+        codec::encode_frame(code, correlation_id, dst, |payload| match self {
+            Request::Reserve {
+                matrix_type,
+                matrix_rows,
+                matrix_columns,
+                priority,
+                operation,
+                conversion,
+            } => {
+                payload.put_u8(u8::from(*matrix_type));
+                payload.put_u32_le(*matrix_rows);
+                payload.put_u32_le(*matrix_columns);
+                payload.put_u8(*priority);
+                payload.put_u8(u8::from(*operation));
+                if let Operation::Multiply(product) = operation {
+                    payload.put_u32_le(product.columns);
+                    payload.put_u8(u8::from(product.arithmetic));
+                }
+                if let Some(conversion) = conversion {
+                    payload.put_u8(u8::from(conversion.matrix_type));
+                    payload.put_u8(u8::from(conversion.arithmetic));
+                }
             }
-            1 => {
-                let id = {
-                    let mut buffer = [0u8; 8];
-                    stream.read_exact(&mut buffer).await?;
-                    usize::from_le_bytes(buffer)
-                };
-
-                Ok(Request::Calc { id })
+            Request::Calc { id, matrix } => {
+                payload.put_slice(&id.to_le_bytes());
+                payload.put_slice(matrix);
             }
-            2 => {
-                let id = {
-                    let mut buffer = [0u8; 8];
-                    stream.read_exact(&mut buffer).await?;
-                    usize::from_le_bytes(buffer)
-                };
-
-                Ok(Request::Poll { id })
+            Request::Poll { id }
+            | Request::Cancel { id }
+            | Request::Resume { id }
+            | Request::Release { id } => payload.put_slice(&id.to_le_bytes()),
+            Request::Upload { id, offset, chunk } => {
+                payload.put_slice(&id.to_le_bytes());
+                payload.put_u64_le(*offset);
+                payload.put_slice(chunk);
             }
-            code => Err(format!("unknown request code: {code}"))?,
-        }
+            Request::Fetch {
+                id,
+                unit,
+                start,
+                count,
+            } => {
+                payload.put_slice(&id.to_le_bytes());
+                payload.put_u8(*unit as u8);
+                payload.put_u64_le(*start);
+                payload.put_u64_le(*count);
+            }
+            Request::Wait { id, timeout_ms } => {
+                payload.put_slice(&id.to_le_bytes());
+                payload.put_u32_le(*timeout_ms);
+            }
+            Request::Hello {
+                protocol_version,
+                client_name,
+            } => {
+                payload.put_u16_le(*protocol_version);
+                if let Some(name) = client_name {
+                    payload.put_slice(name.as_bytes());
+                }
+            }
+            Request::Usage => (),
+        });
     }
 
-    pub async fn execute(self, job_manager: &mut JobManager, stream: &mut TcpStream) -> Response {
+    pub async fn execute(self, job_manager: &JobManager, client: &ClientId) -> Response {
+        // a client that picks a job up on a new connection, e.g. to finish an
+        // interrupted upload, holds it within that connection's quota from now on
+        if let Request::Calc { id, .. }
+        | Request::Upload { id, .. }
+        | Request::Resume { id }
+        | Request::Fetch { id, .. } = &self
+        {
+            job_manager.reattach(*id, client).await;
+        }
         match self {
             Request::Reserve {
                 matrix_type,
-                matrix_dimension,
-            } => match job_manager.reserve(matrix_type, matrix_dimension).await {
+                matrix_rows,
+                matrix_columns,
+                priority,
+                operation,
+                conversion,
+            } => match job_manager
+                .reserve(
+                    client,
+                    matrix_type,
+                    matrix_rows,
+                    matrix_columns,
+                    operation,
+                    conversion,
+                    priority,
+                )
+                .await
+            {
                 Ok(id) => Response::Reserve { id },
                 Err(error) => Response::Error { error },
             },
-            Request::Calc { id } => match job_manager.calc(id, stream).await {
+            Request::Calc { id, matrix } => match job_manager.calc(id, &matrix).await {
                 Ok(()) => Response::Calc,
                 Err(error) => Response::Error { error },
             },
             Request::Poll { id } => Response::Poll {
                 status: job_manager.poll(id).await,
             },
+            Request::Cancel { id } => match job_manager.cancel(id).await {
+                Ok(()) => Response::Cancel,
+                Err(error) => Response::Error { error },
+            },
+            Request::Upload { id, offset, chunk } => {
+                match job_manager.upload(id, offset, &chunk).await {
+                    Ok(received_bytes) => Response::Upload { received_bytes },
+                    Err(error) => Response::Error { error },
+                }
+            }
+            Request::Resume { id } => match job_manager.resume(id).await {
+                Ok(committed_bytes) => Response::Resume { committed_bytes },
+                Err(error) => Response::Error { error },
+            },
+            Request::Fetch {
+                id,
+                unit,
+                start,
+                count,
+            } => match job_manager.fetch(id, unit, start, count).await {
+                Ok(matrix_bytes) => Response::Fetch { matrix_bytes },
+                Err(error) => Response::Error { error },
+            },
+            Request::Wait { id, timeout_ms } => {
+                // a zero timeout means waiting for as long as it takes
+                let timeout = match timeout_ms {
+                    0 => None,
+                    ms => Some(Duration::from_millis(ms as u64)),
+                };
+                Response::Wait {
+                    status: job_manager.wait(id, timeout).await,
+                }
+            }
+            // the connection answers the first hello itself
+            Request::Hello { .. } => Response::Error {
+                error: Error::new(
+                    ErrorCode::ProtocolViolation,
+                    "the handshake has already been done",
+                ),
+            },
+            Request::Usage => Response::Usage {
+                usage: job_manager.usage(client),
+            },
+            Request::Release { id } => match job_manager.release(id).await {
+                Ok(()) => Response::Release,
+                Err(error) => Response::Error { error },
+            },
         }
     }
 
-    pub fn to_json_string(&self, client_id: u16) -> String {
+    pub fn to_json_string(&self, client_id: u16, correlation_id: u32) -> String {
         format!(
-            r#"{{"client":"{}","time":"{}","kind":"{}",{}}}"#,
+            r#"{{"client":"{}","correlation":"{}","time":"{}","kind":"{}",{}}}"#,
             client_id,
+            correlation_id,
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
@@ -113,20 +343,98 @@ impl Request {
                 match self {
                     Request::Reserve {
                         matrix_type,
-                        matrix_dimension,
+                        matrix_rows,
+                        matrix_columns,
+                        priority,
+                        operation,
+                        conversion,
                     } => {
                         json = format!(
-                            r#"{},"matrixType":"{}","matrixDimension":"{}""#,
+                            r#"{},"matrixType":"{}","matrixRows":"{}","matrixColumns":"{}","priority":"{}","operation":"{}""#,
                             json,
                             String::from(*matrix_type),
-                            matrix_dimension
-                        )
+                            matrix_rows,
+                            matrix_columns,
+                            priority,
+                            String::from(*operation)
+                        );
+                        if let Some(conversion) = conversion {
+                            json = format!(
+                                r#"{},"outputType":"{}""#,
+                                json,
+                                String::from(conversion.matrix_type)
+                            )
+                        }
+                    }
+                    Request::Calc { id, matrix } => {
+                        json = format!(r#"{},"id":"{}","length":"{}""#, json, id, matrix.len())
                     }
-                    Request::Calc { id } => json = format!(r#"{},"id":"{}""#, json, id),
                     Request::Poll { id } => json = format!(r#"{},"id":"{}""#, json, id),
+                    Request::Cancel { id } => json = format!(r#"{},"id":"{}""#, json, id),
+                    Request::Upload { id, offset, chunk } => {
+                        json = format!(
+                            r#"{},"id":"{}","offset":"{}","chunkLength":"{}""#,
+                            json,
+                            id,
+                            offset,
+                            chunk.len()
+                        )
+                    }
+                    Request::Resume { id } => json = format!(r#"{},"id":"{}""#, json, id),
+                    Request::Fetch {
+                        id,
+                        unit,
+                        start,
+                        count,
+                    } => {
+                        json = format!(
+                            r#"{},"id":"{}","unit":"{}","start":"{}","count":"{}""#,
+                            json,
+                            id,
+                            String::from(*unit),
+                            start,
+                            count
+                        )
+                    }
+                    Request::Wait { id, timeout_ms } => {
+                        json = format!(r#"{},"id":"{}","timeoutMs":"{}""#, json, id, timeout_ms)
+                    }
+                    Request::Hello {
+                        protocol_version,
+                        client_name,
+                    } => {
+                        json = format!(r#"{},"protocolVersion":"{}""#, json, protocol_version);
+                        // the name is the client's own, so it's escaped
+                        if let Some(name) = client_name {
+                            json = format!(
+                                r#"{},"clientName":{}"#,
+                                json,
+                                serde_json::to_string(name).unwrap()
+                            )
+                        }
+                    }
+                    Request::Usage => (),
+                    Request::Release { id } => json = format!(r#"{},"id":"{}""#, json, id),
                 }
                 json
             }
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_names_are_escaped_in_the_log() {
+        let name = "\"},\"kind\":\"response\n\\";
+        let request = Request::Hello {
+            protocol_version: 2,
+            client_name: Some(String::from(name)),
+        };
+        let json: serde_json::Value = serde_json::from_str(&request.to_json_string(1, 2)).unwrap();
+        assert_eq!(json["clientName"], name);
+        assert_eq!(json["kind"], "request");
+    }
+}
@@ -1,17 +1,57 @@
-use std::error::Error;
+use bytes::{BufMut, Bytes, BytesMut};
 use std::format;
+#[cfg(test)]
+use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
 
+use crate::codec;
+use crate::error::{Error, ErrorCode};
+use crate::quota::Usage;
 use crate::status::Status;
+use crate::token::Token;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Response {
-    Reserve { id: usize },
+    Reserve {
+        id: Token,
+    },
     Calc,
-    Poll { status: Status },
-    Error { error: String },
+    Poll {
+        status: Status,
+    },
+    Error {
+        error: Error,
+    },
+    Cancel,
+    Upload {
+        received_bytes: u64,
+    },
+    Resume {
+        committed_bytes: u64,
+    },
+    Fetch {
+        matrix_bytes: Bytes,
+    },
+    Wait {
+        status: Status,
+    },
+    Hello {
+        protocol_version: u16,
+        // bit n is set if the matrix type with code n is supported
+        matrix_types: u16,
+        max_dimension: u32,
+        max_frame_len: u64,
+        // bit n is set if the request with code n is supported
+        request_codes: u32,
+        // bit n is set if the operation with code n is supported
+        operations: u32,
+        // bit n is set if the optional request field n is supported
+        capabilities: u32,
+    },
+    Usage {
+        usage: Usage,
+    },
+    Release,
 }
 
 impl std::convert::From<&Response> for u8 {
@@ -21,6 +61,14 @@ impl std::convert::From<&Response> for u8 {
             Response::Calc => 1,
             Response::Poll { .. } => 2,
             Response::Error { .. } => 3,
+            Response::Cancel => 4,
+            Response::Upload { .. } => 5,
+            Response::Resume { .. } => 6,
+            Response::Fetch { .. } => 7,
+            Response::Wait { .. } => 8,
+            Response::Hello { .. } => 9,
+            Response::Usage { .. } => 10,
+            Response::Release => 11,
         }
     }
 }
@@ -32,40 +80,141 @@ impl std::convert::From<&Response> for String {
             Response::Calc => String::from("calc"),
             Response::Poll { .. } => String::from("poll"),
             Response::Error { .. } => String::from("error"),
+            Response::Cancel => String::from("cancel"),
+            Response::Upload { .. } => String::from("upload"),
+            Response::Resume { .. } => String::from("resume"),
+            Response::Fetch { .. } => String::from("fetch"),
+            Response::Wait { .. } => String::from("wait"),
+            Response::Hello { .. } => String::from("hello"),
+            Response::Usage { .. } => String::from("usage"),
+            Response::Release => String::from("release"),
         }
     }
 }
 
 impl Response {
-    pub async fn send(self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
-        let response_code = u8::from(&self);
-        stream.write_all(&[response_code]).await?;
-
-        match self {
-            Response::Reserve { id } => stream.write_all(&id.to_le_bytes()).await?,
-            Response::Calc => (),
-            Response::Poll { status } => {
-                let status_code = u8::from(&status);
-                stream.write_all(&[status_code]).await?;
-
-                if let Status::Completed { matrix_bytes } = status {
-                    stream.write_all(&matrix_bytes).await?;
-                }
+    pub fn encode(&self, correlation_id: u32, dst: &mut BytesMut) {
+        codec::encode_frame(u8::from(self), correlation_id, dst, |payload| match self {
+            Response::Reserve { id } => payload.put_slice(&id.to_le_bytes()),
+            Response::Calc | Response::Cancel | Response::Release => (),
+            Response::Poll { status } | Response::Wait { status } => status.encode(payload),
+            Response::Upload { received_bytes } => payload.put_u64_le(*received_bytes),
+            Response::Resume { committed_bytes } => payload.put_u64_le(*committed_bytes),
+            Response::Fetch { matrix_bytes } => payload.put_slice(matrix_bytes),
+            Response::Hello {
+                protocol_version,
+                matrix_types,
+                max_dimension,
+                max_frame_len,
+                request_codes,
+                operations,
+                capabilities,
+            } => {
+                payload.put_u16_le(*protocol_version);
+                payload.put_u16_le(*matrix_types);
+                payload.put_u32_le(*max_dimension);
+                payload.put_u64_le(*max_frame_len);
+                payload.put_u32_le(*request_codes);
+                payload.put_u32_le(*operations);
+                payload.put_u32_le(*capabilities);
+            }
+            Response::Usage { usage } => {
+                payload.put_u32_le(usage.jobs);
+                payload.put_u32_le(usage.max_jobs);
+                payload.put_u64_le(usage.bytes);
+                payload.put_u64_le(usage.max_bytes);
+                payload.put_u32_le(usage.running);
+                payload.put_u32_le(usage.max_running);
             }
             Response::Error { error } => {
-                let error = error.as_bytes();
-                stream.write_all(&[error.len() as u8]).await?;
-                stream.write_all(error).await?;
+                payload.put_u16_le(u16::from(error.code));
+                payload.put_u32_le(error.message.len() as u32);
+                payload.put_slice(error.message.as_bytes());
+                if error.code == ErrorCode::ServerBusy {
+                    let retry_after = error.retry_after.unwrap_or_default().as_millis();
+                    payload.put_u32_le(retry_after.min(u32::MAX as u128) as u32);
+                }
+            }
+        });
+    }
+
+    // the client side of the protocol
+    #[cfg(test)]
+    pub fn decode(code: u8, mut payload: Bytes) -> Result<Response, Error> {
+        let response = match code {
+            0 => Response::Reserve {
+                id: codec::read_token(&mut payload)?,
+            },
+            1 => Response::Calc,
+            2 => Response::Poll {
+                status: Status::decode(&mut payload)?,
+            },
+            3 => {
+                let code = ErrorCode::try_from(codec::read_u16(&mut payload)?)?;
+                let len = codec::read_u32(&mut payload)? as usize;
+                if payload.len() < len {
+                    Err(Error::new(
+                        ErrorCode::MalformedMessage,
+                        "the message is too short",
+                    ))?
+                }
+                let message = String::from_utf8(payload.split_to(len).to_vec())
+                    .map_err(|e| Error::new(ErrorCode::MalformedMessage, e.to_string()))?;
+                let mut error = Error::new(code, message);
+                if code == ErrorCode::ServerBusy {
+                    let retry_after = codec::read_u32(&mut payload)? as u64;
+                    error = error.with_retry_after(Duration::from_millis(retry_after));
+                }
+                Response::Error { error }
             }
+            4 => Response::Cancel,
+            5 => Response::Upload {
+                received_bytes: codec::read_u64(&mut payload)?,
+            },
+            6 => Response::Resume {
+                committed_bytes: codec::read_u64(&mut payload)?,
+            },
+            7 => Response::Fetch {
+                matrix_bytes: std::mem::take(&mut payload),
+            },
+            8 => Response::Wait {
+                status: Status::decode(&mut payload)?,
+            },
+            9 => Response::Hello {
+                protocol_version: codec::read_u16(&mut payload)?,
+                matrix_types: codec::read_u16(&mut payload)?,
+                max_dimension: codec::read_u32(&mut payload)?,
+                max_frame_len: codec::read_u64(&mut payload)?,
+                request_codes: codec::read_u32(&mut payload)?,
+                operations: codec::read_u32(&mut payload)?,
+                capabilities: codec::read_u32(&mut payload)?,
+            },
+            10 => Response::Usage {
+                usage: Usage {
+                    jobs: codec::read_u32(&mut payload)?,
+                    max_jobs: codec::read_u32(&mut payload)?,
+                    bytes: codec::read_u64(&mut payload)?,
+                    max_bytes: codec::read_u64(&mut payload)?,
+                    running: codec::read_u32(&mut payload)?,
+                    max_running: codec::read_u32(&mut payload)?,
+                },
+            },
+            11 => Response::Release,
+            code => Err(Error::new(
+                ErrorCode::UnknownRequest,
+                format!("unknown response code: {code}"),
+            ))?,
         };
+        codec::read_end(&payload)?;
 
-        Ok(())
+        Ok(response)
     }
 
-    pub fn to_json_string(&self, client_id: u16) -> String {
+    pub fn to_json_string(&self, client_id: u16, correlation_id: u32) -> String {
         format!(
-            r#"{{"client":"{}","time":"{}","kind":"{}",{}}}"#,
+            r#"{{"client":"{}","correlation":"{}","time":"{}","kind":"{}",{}}}"#,
             client_id,
+            correlation_id,
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
@@ -75,12 +224,39 @@ impl Response {
                 let mut json = format!(r#""type":"{}""#, String::from(self));
                 match self {
                     Response::Reserve { id } => json = format!(r#"{},"id":"{}""#, json, id),
-                    Response::Calc => (),
-                    Response::Poll { status } => {
+                    Response::Calc | Response::Cancel | Response::Release => (),
+                    Response::Poll { status } | Response::Wait { status } => {
                         json = format!(r#"{},"status":"{}""#, json, String::from(status));
                     }
                     Response::Error { error } => {
-                        json = format!(r#"{},"message":"{}""#, json, error)
+                        json = format!(
+                            r#"{},"code":"{}","message":"{}""#,
+                            json,
+                            String::from(error.code),
+                            error.message
+                        );
+                        if let Some(retry_after) = error.retry_after {
+                            json =
+                                format!(r#"{},"retryAfterMs":"{}""#, json, retry_after.as_millis())
+                        }
+                    }
+                    Response::Upload { received_bytes } => {
+                        json = format!(r#"{},"receivedBytes":"{}""#, json, received_bytes)
+                    }
+                    Response::Resume { committed_bytes } => {
+                        json = format!(r#"{},"committedBytes":"{}""#, json, committed_bytes)
+                    }
+                    Response::Fetch { matrix_bytes } => {
+                        json = format!(r#"{},"length":"{}""#, json, matrix_bytes.len())
+                    }
+                    Response::Hello {
+                        protocol_version, ..
+                    } => json = format!(r#"{},"protocolVersion":"{}""#, json, protocol_version),
+                    Response::Usage { usage } => {
+                        json = format!(
+                            r#"{},"jobs":"{}","bytes":"{}","running":"{}""#,
+                            json, usage.jobs, usage.bytes, usage.running
+                        )
                     }
                 }
                 json
@@ -0,0 +1,173 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::quota::ClientId;
+
+/// The order in which the jobs waiting for the thread pool are started. Jobs
+/// with a higher priority always go first, the policy decides between jobs of
+/// the same priority.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    // in the order the matrices were received
+    Fifo,
+    // the clients take turns, so a client with a lot of jobs doesn't hold up the others
+    FairShare,
+    SmallestFirst,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "fifo" => Ok(Policy::Fifo),
+            "fair-share" => Ok(Policy::FairShare),
+            "smallest-first" => Ok(Policy::SmallestFirst),
+            _ => Err(format!(
+                "unknown scheduling policy {value}, must be fifo, fair-share or smallest-first"
+            )),
+        }
+    }
+}
+
+struct Queued<T> {
+    item: T,
+    priority: u8,
+    client: ClientId,
+    len: usize,
+    // the order the job was queued in
+    seq: u64,
+}
+
+/// The jobs waiting for the thread pool.
+pub struct Scheduler<T> {
+    policy: Policy,
+    queue: Vec<Queued<T>>,
+    next_seq: u64,
+    // the turn at which each client with queued jobs was last served
+    last_served: HashMap<ClientId, u64>,
+    turn: u64,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new(policy: Policy) -> Scheduler<T> {
+        Scheduler {
+            policy,
+            queue: Vec::new(),
+            next_seq: 0,
+            last_served: HashMap::new(),
+            turn: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T, priority: u8, client: ClientId, len: usize) {
+        self.queue.push(Queued {
+            item,
+            priority,
+            client,
+            len,
+            seq: self.next_seq,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Takes the job that should be started next.
+    pub fn pop(&mut self) -> Option<T> {
+        // there are only as many queued jobs as there are matrices in memory,
+        // so a linear search is fast enough
+        let index = (0..self.queue.len()).min_by_key(|&i| {
+            let queued = &self.queue[i];
+            let policy_key = match self.policy {
+                Policy::Fifo => 0,
+                Policy::FairShare => self.last_served.get(&queued.client).copied().unwrap_or(0),
+                Policy::SmallestFirst => queued.len as u64,
+            };
+            (Reverse(queued.priority), policy_key, queued.seq)
+        })?;
+        let queued = self.queue.swap_remove(index);
+
+        self.turn += 1;
+        if self.queue.iter().any(|other| other.client == queued.client) {
+            self.last_served.insert(queued.client, self.turn);
+        } else {
+            self.last_served.remove(&queued.client);
+        }
+        Some(queued.item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clients<const N: usize>() -> [ClientId; N] {
+        std::array::from_fn(|_| ClientId::new(None))
+    }
+
+    fn order(scheduler: &mut Scheduler<&'static str>) -> Vec<&'static str> {
+        std::iter::from_fn(|| scheduler.pop()).collect()
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("fifo".parse(), Ok(Policy::Fifo));
+        assert_eq!("fair-share".parse(), Ok(Policy::FairShare));
+        assert_eq!("smallest-first".parse(), Ok(Policy::SmallestFirst));
+        assert!("random".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn fifo_keeps_the_order_within_a_priority() {
+        let mut scheduler = Scheduler::new(Policy::Fifo);
+        let [a, b] = clients();
+        scheduler.push("a1", 0, a.clone(), 100);
+        scheduler.push("a2", 0, a.clone(), 1);
+        scheduler.push("b1", 1, b.clone(), 100);
+        scheduler.push("b2", 0, b.clone(), 1);
+
+        assert_eq!(order(&mut scheduler), ["b1", "a1", "a2", "b2"]);
+    }
+
+    #[test]
+    fn fair_share_lets_clients_take_turns() {
+        let mut scheduler = Scheduler::new(Policy::FairShare);
+        let [a, b, c] = clients();
+        for job in ["a1", "a2", "a3", "a4"] {
+            scheduler.push(job, 0, a.clone(), 100);
+        }
+        scheduler.push("b1", 0, b.clone(), 1);
+        scheduler.push("b2", 0, b.clone(), 1);
+
+        assert_eq!(scheduler.pop(), Some("a1"));
+        // a client that comes later gets its turn right away
+        scheduler.push("c1", 0, c.clone(), 1);
+        assert_eq!(order(&mut scheduler), ["b1", "c1", "a2", "b2", "a3", "a4"]);
+    }
+
+    #[test]
+    fn fair_share_goes_by_priority_first() {
+        let mut scheduler = Scheduler::new(Policy::FairShare);
+        let [a, b] = clients();
+        scheduler.push("a1", 2, a.clone(), 100);
+        scheduler.push("a2", 2, a.clone(), 100);
+        scheduler.push("b1", 1, b.clone(), 1);
+
+        assert_eq!(order(&mut scheduler), ["a1", "a2", "b1"]);
+    }
+
+    #[test]
+    fn smallest_first_starts_small_jobs_first() {
+        let mut scheduler = Scheduler::new(Policy::SmallestFirst);
+        let [a, b, c] = clients();
+        scheduler.push("large", 0, a.clone(), 1000);
+        scheduler.push("small", 0, a.clone(), 10);
+        scheduler.push("medium", 0, b.clone(), 100);
+        scheduler.push("small too", 0, b.clone(), 10);
+        scheduler.push("urgent", 1, c.clone(), 10_000);
+
+        assert_eq!(
+            order(&mut scheduler),
+            ["urgent", "small", "small too", "medium", "large"]
+        );
+    }
+}
@@ -1,20 +1,42 @@
+use bytes::{BufMut, Bytes, BytesMut};
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[cfg(test)]
+use crate::{
+    codec,
+    error::{Error, ErrorCode},
+};
+
+// the status code and the dimensions that come before the data of a completed result
+pub const COMPLETED_HEADER_LEN: u64 = 9;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum Status {
     NoData,
-    Reserved,
-    Running,
-    Completed { matrix_bytes: Vec<u8> },
+    Reserved {
+        received_bytes: u64,
+        total_bytes: u64,
+    },
+    Running {
+        // the number of jobs waiting for the thread pool, server-wide
+        queue_depth: u32,
+    },
+    Completed {
+        matrix_rows: u32,
+        matrix_columns: u32,
+        matrix_bytes: Bytes,
+    },
+    Cancelled,
 }
 
 impl std::convert::From<&Status> for String {
     fn from(value: &Status) -> Self {
         match value {
             Status::NoData => String::from("no data"),
-            Status::Reserved => String::from("reserved"),
-            Status::Running => String::from("running"),
+            Status::Reserved { .. } => String::from("reserved"),
+            Status::Running { .. } => String::from("running"),
             Status::Completed { .. } => String::from("completed"),
+            Status::Cancelled => String::from("cancelled"),
         }
     }
 }
@@ -23,9 +45,62 @@ impl std::convert::From<&Status> for u8 {
     fn from(value: &Status) -> Self {
         match value {
             Status::NoData => 0,
-            Status::Reserved => 1,
-            Status::Running => 2,
+            Status::Reserved { .. } => 1,
+            Status::Running { .. } => 2,
             Status::Completed { .. } => 3,
+            Status::Cancelled => 4,
+        }
+    }
+}
+
+impl Status {
+    pub fn encode(&self, payload: &mut BytesMut) {
+        payload.put_u8(u8::from(self));
+        match self {
+            Status::Reserved {
+                received_bytes,
+                total_bytes,
+            } => {
+                payload.put_u64_le(*received_bytes);
+                payload.put_u64_le(*total_bytes);
+            }
+            Status::Completed {
+                matrix_rows,
+                matrix_columns,
+                matrix_bytes,
+            } => {
+                payload.put_u32_le(*matrix_rows);
+                payload.put_u32_le(*matrix_columns);
+                payload.put_slice(matrix_bytes);
+            }
+            Status::Running { queue_depth } => payload.put_u32_le(*queue_depth),
+            Status::NoData | Status::Cancelled => (),
         }
     }
+
+    // a completed status takes the rest of the payload
+    #[cfg(test)]
+    pub fn decode(payload: &mut Bytes) -> Result<Status, Error> {
+        let status = match codec::read_u8(payload)? {
+            0 => Status::NoData,
+            1 => Status::Reserved {
+                received_bytes: codec::read_u64(payload)?,
+                total_bytes: codec::read_u64(payload)?,
+            },
+            2 => Status::Running {
+                queue_depth: codec::read_u32(payload)?,
+            },
+            3 => Status::Completed {
+                matrix_rows: codec::read_u32(payload)?,
+                matrix_columns: codec::read_u32(payload)?,
+                matrix_bytes: std::mem::take(payload),
+            },
+            4 => Status::Cancelled,
+            code => Err(Error::new(
+                ErrorCode::MalformedMessage,
+                format!("unknown status code: {code}"),
+            ))?,
+        };
+        Ok(status)
+    }
 }
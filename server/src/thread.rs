@@ -1,45 +1,136 @@
+use futures::{SinkExt, StreamExt};
 use std::{
+    collections::HashSet,
     eprintln,
-    sync::Arc,
+    sync::{Arc, Mutex},
     time::{SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::Mutex;
-use tokio::{net::TcpStream, sync::mpsc::UnboundedSender};
+use tokio::net::{tcp::OwnedWriteHalf, TcpStream};
+use tokio::sync::{
+    mpsc::{self, Receiver},
+    Semaphore,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
 
 use crate::{
-    job::{self, MatrixData, Task},
+    codec::ServerCodec,
+    config::Config,
+    error::{Error, ErrorCode},
+    handshake,
+    job::JobManager,
+    quota::ClientId,
     request::Request,
+    response::Response,
 };
 
-pub async fn handle_client(
-    mut stream: TcpStream,
-    tx: UnboundedSender<(MatrixData, Arc<Mutex<Task>>)>,
-) {
+pub async fn handle_client(stream: TcpStream, job_manager: Arc<JobManager>, config: Arc<Config>) {
     let port = stream.peer_addr().unwrap().port();
-    let mut job_manager = job::new_manager(tx);
+    let (read_stream, write_stream) = stream.into_split();
+    let mut requests = FramedRead::new(read_stream, ServerCodec::new(config.max_frame_len));
+    let responses = FramedWrite::new(write_stream, ServerCodec::new(config.max_frame_len));
+
+    // responses are written by a separate task in the order they become ready,
+    // so a slow request doesn't hold up the ones pipelined after it. A client
+    // that doesn't read its responses fills the channel up, which holds up the
+    // requests, which in turn stop the connection from being read
+    let (responses_tx, responses_rx) = mpsc::channel(config.max_in_flight_requests);
+    tokio::spawn(send_responses(responses, responses_rx, port));
+    let permits = Arc::new(Semaphore::new(config.max_in_flight_requests));
+
+    // correlation IDs of the requests that haven't been answered yet
+    let in_flight = Arc::new(Mutex::new(HashSet::new()));
+    // known once the handshake is done
+    let mut client = None;
 
     loop {
-        let request = match Request::from_stream(&mut stream).await {
+        let (correlation_id, request) = match requests.next().await {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => {
+                eprintln!("Error reading request: {}", error);
+                break;
+            }
+            None => {
+                eprintln!("The client {port} disconnected");
+                break;
+            }
+        };
+
+        // the frame was read whole, so a malformed request doesn't break the stream
+        let request = match request {
             Ok(request) => request,
-            Err(error) => match error.downcast::<std::io::Error>() {
-                Ok(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    eprintln!("The client {port} disconnected");
-                    break;
+            Err(error) => {
+                eprintln!("Error parsing request: {}", error);
+                let _ = responses_tx
+                    .send((correlation_id, Response::Error { error }))
+                    .await;
+                continue;
+            }
+        };
+        println!("{}", request.to_json_string(port, correlation_id));
+
+        let Some(client) = &client else {
+            let response = match &request {
+                Request::Hello {
+                    protocol_version,
+                    client_name,
+                } => handshake::hello(*protocol_version, client_name.as_deref(), &config),
+                _ => Err(Error::new(
+                    ErrorCode::ProtocolViolation,
+                    "the first request must be a hello",
+                )),
+            };
+            match response {
+                Ok(response) => {
+                    if let Request::Hello { client_name, .. } = request {
+                        client = Some(ClientId::new(client_name));
+                    }
+                    let _ = responses_tx.send((correlation_id, response)).await;
                 }
-                err => {
-                    eprintln!(
-                        "Error parsing request: {}",
-                        err.map_or_else(|e| e.to_string(), |e| e.to_string())
-                    );
+                Err(error) => {
+                    eprintln!("The client {port} failed the handshake: {error}");
+                    let _ = responses_tx
+                        .send((correlation_id, Response::Error { error }))
+                        .await;
                     break;
                 }
-            },
+            }
+            continue;
         };
-        println!("{}", request.to_json_string(port));
 
-        let response = request.execute(&mut job_manager, &mut stream).await;
-        let response_json = response.to_json_string(port);
-        match response.send(&mut stream).await {
+        if !in_flight.lock().unwrap().insert(correlation_id) {
+            let error = Error::new(
+                ErrorCode::DuplicateCorrelationId,
+                format!("the correlation id {correlation_id} is already in use"),
+            );
+            let _ = responses_tx
+                .send((correlation_id, Response::Error { error }))
+                .await;
+            continue;
+        }
+
+        // the semaphore is never closed
+        let permit = Arc::clone(&permits).acquire_owned().await.unwrap();
+        let job_manager = Arc::clone(&job_manager);
+        let in_flight = Arc::clone(&in_flight);
+        let responses_tx = responses_tx.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let response = request.execute(&job_manager, &client).await;
+            in_flight.lock().unwrap().remove(&correlation_id);
+            let _ = responses_tx.send((correlation_id, response)).await;
+            drop(permit);
+        });
+    }
+}
+
+async fn send_responses(
+    mut stream: FramedWrite<OwnedWriteHalf, ServerCodec>,
+    mut responses_rx: Receiver<(u32, Response)>,
+    port: u16,
+) {
+    while let Some((correlation_id, response)) = responses_rx.recv().await {
+        let response_json = response.to_json_string(port, correlation_id);
+        match stream.send((correlation_id, response)).await {
             Ok(()) => (),
             Err(error) => {
                 eprintln!("Error sending response: {}", error);
@@ -61,3 +152,205 @@ fn json_replace(json: &str, key: &str, new_value: &str) -> String {
     let end = begin + json[begin..].find('"').unwrap();
     format!("{}{}{}", &json[..begin], new_value, &json[end..])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use std::{collections::HashMap, time::Duration};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::{
+        codec, job, matrix_type::MatrixType, memory::MemoryLedger, operation::Operation,
+        queue::JobQueue, quota::QuotaLedger, status::Status, token::Token,
+    };
+
+    // a connection to a server that has said hello already. The uploaded jobs
+    // never start, as nothing takes them off the channel
+    async fn connect() -> (TcpStream, BytesMut) {
+        connect_with(Config::from_args(&[]).unwrap()).await
+    }
+
+    async fn connect_with(config: Config) -> (TcpStream, BytesMut) {
+        let config = Arc::new(config);
+        let (tx, rx) = mpsc::channel(config.max_queued_jobs);
+        let job_manager = Arc::new(job::new_manager(
+            tx,
+            config.upload_grace_period,
+            config.result_ttl,
+            Arc::new(MemoryLedger::new(config.memory_cap)),
+            config.job_limits(),
+            Arc::new(QuotaLedger::new(config.quota)),
+            Arc::new(JobQueue::new(config.max_queued_jobs)),
+            config.max_frame_len,
+        ));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // kept open, so that uploads still find room for their jobs
+            let _rx = rx;
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_client(stream, job_manager, config).await;
+        });
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut buffer = BytesMut::new();
+        let hello = Request::Hello {
+            protocol_version: handshake::PROTOCOL_VERSION,
+            client_name: None,
+        };
+        send(&mut stream, &[(0, hello)]).await;
+        let (_, response) = receive(&mut stream, &mut buffer).await;
+        assert!(matches!(response, Response::Hello { .. }), "{response:?}");
+        (stream, buffer)
+    }
+
+    // all the requests are written at once, without waiting for any response
+    async fn send(stream: &mut TcpStream, requests: &[(u32, Request)]) {
+        let mut frames = BytesMut::new();
+        for (correlation_id, request) in requests {
+            request.encode(*correlation_id, &mut frames);
+        }
+        stream.write_all(&frames).await.unwrap();
+    }
+
+    async fn receive(stream: &mut TcpStream, buffer: &mut BytesMut) -> (u32, Response) {
+        loop {
+            if let Some(frame) = codec::decode_frame(buffer, u64::MAX).unwrap() {
+                let response = Response::decode(frame.code, frame.payload).unwrap();
+                return (frame.correlation_id, response);
+            }
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read_buf(buffer))
+                .await
+                .expect("no response in time")
+                .unwrap();
+            assert_ne!(read, 0, "the server disconnected");
+        }
+    }
+
+    async fn reserve(stream: &mut TcpStream, buffer: &mut BytesMut) -> Token {
+        let request = Request::Reserve {
+            matrix_type: MatrixType::U8,
+            matrix_rows: 2,
+            matrix_columns: 3,
+            priority: 0,
+            operation: Operation::Transpose,
+            conversion: None,
+        };
+        send(stream, &[(1, request)]).await;
+        match receive(stream, buffer).await {
+            (1, Response::Reserve { id }) => id,
+            response => panic!("unexpected response to reserve: {response:?}"),
+        }
+    }
+
+    fn reserved() -> Status {
+        Status::Reserved {
+            received_bytes: 0,
+            total_bytes: 6,
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_carry_the_correlation_ids_of_their_requests() {
+        let (mut stream, mut buffer) = connect().await;
+        let id = reserve(&mut stream, &mut buffer).await;
+
+        let correlation_ids = [2, 0x8000_0000, u32::MAX];
+        let requests: Vec<_> = correlation_ids
+            .iter()
+            .map(|&correlation_id| (correlation_id, Request::Poll { id }))
+            .collect();
+        send(&mut stream, &requests).await;
+
+        let mut responses = HashMap::new();
+        for _ in correlation_ids {
+            let (correlation_id, response) = receive(&mut stream, &mut buffer).await;
+            responses.insert(correlation_id, response);
+        }
+        for correlation_id in correlation_ids {
+            assert_eq!(
+                responses.remove(&correlation_id),
+                Some(Response::Poll { status: reserved() }),
+                "correlation id {correlation_id}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_answered_as_they_complete() {
+        let (mut stream, mut buffer) = connect().await;
+        let id = reserve(&mut stream, &mut buffer).await;
+
+        // the wait holds its response back, the poll behind it doesn't
+        let wait = Request::Wait {
+            id,
+            timeout_ms: 200,
+        };
+        send(&mut stream, &[(2, wait), (3, Request::Poll { id })]).await;
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (3, Response::Poll { status: reserved() })
+        );
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (2, Response::Wait { status: reserved() })
+        );
+    }
+
+    #[tokio::test]
+    async fn correlation_ids_are_reused_only_after_their_response() {
+        let (mut stream, mut buffer) = connect().await;
+        let id = reserve(&mut stream, &mut buffer).await;
+
+        let wait = Request::Wait {
+            id,
+            timeout_ms: 200,
+        };
+        send(&mut stream, &[(5, wait), (5, Request::Poll { id })]).await;
+        match receive(&mut stream, &mut buffer).await {
+            (5, Response::Error { error }) => {
+                assert_eq!(error.code, ErrorCode::DuplicateCorrelationId)
+            }
+            response => panic!("unexpected response to a duplicate: {response:?}"),
+        }
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (5, Response::Wait { status: reserved() })
+        );
+
+        // answered, so the id is free again
+        send(&mut stream, &[(5, Request::Poll { id })]).await;
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (5, Response::Poll { status: reserved() })
+        );
+    }
+    #[tokio::test]
+    async fn requests_past_the_in_flight_limit_wait_for_a_response() {
+        let config = Config::from_args(&[String::from("--max-in-flight-requests=2")]).unwrap();
+        let (mut stream, mut buffer) = connect_with(config).await;
+        let id = reserve(&mut stream, &mut buffer).await;
+
+        // the poll isn't even read until one of the waits is answered
+        let wait = |timeout_ms| Request::Wait { id, timeout_ms };
+        let requests = [(2, wait(100)), (3, wait(200)), (4, Request::Poll { id })];
+        send(&mut stream, &requests).await;
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (2, Response::Wait { status: reserved() })
+        );
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (4, Response::Poll { status: reserved() })
+        );
+        assert_eq!(
+            receive(&mut stream, &mut buffer).await,
+            (3, Response::Wait { status: reserved() })
+        );
+    }
+}
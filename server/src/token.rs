@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+/// A server-wide job identifier. Tokens are drawn from a cryptographically
+/// secure generator, so knowing one token doesn't help guessing another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Token(u128);
+
+impl Token {
+    pub const LEN: usize = 16;
+
+    pub fn generate() -> Token {
+        Token(rand::random())
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Token::LEN]) -> Token {
+        Token(u128::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Token::LEN] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+/// Byte ranges of a matrix received so far, kept sorted and merged.
+#[derive(Default)]
+pub struct ReceivedRanges(Vec<Range<usize>>);
+
+impl ReceivedRanges {
+    pub fn insert(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let mut merged = range;
+        self.0.retain(|r| {
+            if r.end < merged.start || r.start > merged.end {
+                return true;
+            }
+            merged = merged.start.min(r.start)..merged.end.max(r.end);
+            false
+        });
+        let index = self.0.partition_point(|r| r.start < merged.start);
+        self.0.insert(index, merged);
+    }
+
+    pub fn received(&self) -> usize {
+        self.0.iter().map(|r| r.len()).sum()
+    }
+
+    // the number of bytes that would be received after inserting the range
+    pub fn received_with(&self, range: &Range<usize>) -> usize {
+        let overlap: usize = self
+            .0
+            .iter()
+            .map(|r| {
+                r.end
+                    .min(range.end)
+                    .saturating_sub(r.start.max(range.start))
+            })
+            .sum();
+        self.received() + range.len() - overlap
+    }
+
+    // the length of the part received without gaps
+    pub fn committed(&self) -> usize {
+        match self.0.first() {
+            Some(r) if r.start == 0 => r.end,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let mut ranges = ReceivedRanges::default();
+        ranges.insert(10..20);
+        ranges.insert(30..40);
+        ranges.insert(0..5);
+        assert_eq!(ranges.0, vec![0..5, 10..20, 30..40]);
+        assert_eq!(ranges.received(), 25);
+        assert_eq!(ranges.committed(), 5);
+
+        assert_eq!(ranges.received_with(&(15..30)), 35);
+        assert_eq!(ranges.received_with(&(0..40)), 40);
+        assert_eq!(ranges.received_with(&(40..45)), 30);
+        ranges.insert(15..30);
+        assert_eq!(ranges.0, vec![0..5, 10..40]);
+        ranges.insert(5..10);
+        assert_eq!(ranges.0, vec![0..40]);
+        ranges.insert(20..25);
+        ranges.insert(7..7);
+        assert_eq!(ranges.0, vec![0..40]);
+        assert_eq!(ranges.received(), 40);
+        assert_eq!(ranges.committed(), 40);
+    }
+}
@@ -36,12 +36,14 @@ const MAX_CONNECTIONS_COUNT = 8
 const POLLING_FREQUENCY = 100
 
 func getColumns() []string {
-	return []string{"client", "time", "kind", "type", "matrixType", "matrixDimension", "id", "status", "message"}
+	return []string{"client", "correlation", "time", "kind", "type", "matrixType", "matrixRows", "matrixColumns",
+		"id", "status", "code", "message"}
 }
 
 type Matrix struct {
-	Type      string
-	Dimension uint32
+	Type    string
+	Rows    uint32
+	Columns uint32
 }
 
 func (matrix Matrix) getTypeBitSize() uint8 {
@@ -54,31 +56,32 @@ func (matrix Matrix) getTypeByteSize() uint8 {
 }
 
 func (matrix Matrix) getByteLength() uint64 {
-	return uint64(matrix.Dimension) * uint64(matrix.Dimension) * uint64(matrix.getTypeByteSize())
+	return uint64(matrix.Rows) * uint64(matrix.Columns) * uint64(matrix.getTypeByteSize())
 }
 
 func (matrix Matrix) getRowByteLength() uint32 {
-	return matrix.Dimension * uint32(matrix.getTypeByteSize())
+	return matrix.Columns * uint32(matrix.getTypeByteSize())
 }
 
 func (matrix Matrix) String() string {
-	return fmt.Sprintf("%dx%d matrix of type %s", matrix.Dimension, matrix.Dimension, matrix.Type)
+	return fmt.Sprintf("%dx%d matrix of type %s", matrix.Rows, matrix.Columns, matrix.Type)
 }
 
 func getMatrixTypes() []string {
 	return []string{"u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "f32", "f64"}
 }
 
-func getMatrixDimensions() []uint32 {
-	return []uint32{10, 100, 1000, 10000}
+// rows and columns
+func getMatrixShapes() [][2]uint32 {
+	return [][2]uint32{{10, 10}, {100, 100}, {1000, 1000}, {10000, 10000}, {3, 700}, {1000, 30}}
 }
 
 func getMatrixList() *list.List {
 	list := list.New()
 
 	for _, mType := range getMatrixTypes() {
-		for _, dimension := range getMatrixDimensions() {
-			list.PushBack(Matrix{Type: mType, Dimension: dimension})
+		for _, shape := range getMatrixShapes() {
+			list.PushBack(Matrix{Type: mType, Rows: shape[0], Columns: shape[1]})
 		}
 	}
 
@@ -86,7 +89,7 @@ func getMatrixList() *list.List {
 }
 
 func getFileName(matrix Matrix) string {
-	return fmt.Sprintf("%s_%d_matrix", matrix.Type, matrix.Dimension)
+	return fmt.Sprintf("%s_%dx%d_matrix", matrix.Type, matrix.Rows, matrix.Columns)
 }
 
 func getTestFilePath(matrix Matrix) string {
@@ -113,7 +116,7 @@ func jsonExtractValue(json string, key string) (value string) {
 	return
 }
 
-func runCommand(commandString string, objects chan string) (id uint64, completed bool, err error) {
+func runCommand(commandString string, objects chan string) (id string, completed bool, err error) {
 	tokens := strings.Split(commandString, " ")
 	cmd := exec.Command(CLIENT_EXEC, tokens...)
 	cmd.Dir = CLIENT_FOLDER
@@ -138,11 +141,7 @@ func runCommand(commandString string, objects chan string) (id uint64, completed
 		fmt.Fprintf(os.Stderr, "command %s invalid output: %s %s", commandString, l, r)
 	}
 
-	str := jsonExtractValue(r, "id")
-	if str != "" {
-		id, _ = strconv.ParseUint(str, 10, 64)
-	}
-
+	id = jsonExtractValue(r, "id")
 	completed = jsonExtractValue(r, "status") == "completed"
 
 	objects <- l
@@ -160,39 +159,36 @@ func handleClient(clientId uint16, daemonId uint16, objects chan string) {
 		queue.Remove(m)
 		matrix := m.Value.(Matrix)
 
-		mType := matrix.Type
-		mDimension := matrix.Dimension
-
 		fmt.Printf("Client %d: running reserve for a %s\n", clientId, matrix)
-		id, _, err := runCommand(fmt.Sprintf("--id=%d --command=reserve --type=%s --dimension=%d --file=%s",
-			daemonId, mType, mDimension, getTestFilePath(matrix)), objects)
+		id, _, err := runCommand(fmt.Sprintf("--id=%d --command=reserve --type=%s --rows=%d --columns=%d --file=%s",
+			daemonId, matrix.Type, matrix.Rows, matrix.Columns, getTestFilePath(matrix)), objects)
 		if err != nil {
 			fmt.Fprintf(os.Stderr, "Client %d: Error running reserve for a %s: %s", clientId, matrix, err)
 			os.Exit(1)
 		}
 
-		if id == 0 {
+		if id == "" {
 			fmt.Printf("Client %d: The server's memory is full. Moving the %s to the end of the queue", clientId, matrix)
 			queue.PushBack(matrix)
 			continue
 		}
 
-		fmt.Printf("Client %d: running calc for the %s (job id %d)\n",
+		fmt.Printf("Client %d: running calc for the %s (job id %s)\n",
 			clientId, matrix, id)
-		_, _, err = runCommand(fmt.Sprintf("--id=%d --command=calc --job-id=%d",
+		_, _, err = runCommand(fmt.Sprintf("--id=%d --command=calc --job-id=%s",
 			daemonId, id), objects)
 		if err != nil {
-			fmt.Fprintf(os.Stderr, "Client %d: Error running calc for the %s (job id %d): %s",
+			fmt.Fprintf(os.Stderr, "Client %d: Error running calc for the %s (job id %s): %s",
 				clientId, matrix, id, err)
 			os.Exit(1)
 		}
 
-		fmt.Printf("Client %d: running poll for the %s (job id %d)\n", clientId, matrix, id)
+		fmt.Printf("Client %d: running poll for the %s (job id %s)\n", clientId, matrix, id)
 		pollingFrequency := time.Millisecond * POLLING_FREQUENCY
 		for {
-			_, completed, err := runCommand(fmt.Sprintf("--id=%d --command=poll --job-id=%d", daemonId, id), objects)
+			_, completed, err := runCommand(fmt.Sprintf("--id=%d --command=poll --job-id=%s", daemonId, id), objects)
 			if err != nil {
-				fmt.Fprintf(os.Stderr, "Client %d: Error running poll for the %s (job id %d): %s",
+				fmt.Fprintf(os.Stderr, "Client %d: Error running poll for the %s (job id %s): %s",
 					clientId, matrix, id, err)
 				os.Exit(1)
 			}
@@ -237,7 +233,7 @@ func generateMatrix(matrix Matrix) error {
 
 	go func() {
 		rowLen := matrix.getRowByteLength()
-		for i := uint32(0); i < matrix.Dimension; i++ {
+		for i := uint32(0); i < matrix.Rows; i++ {
 			buffer := make([]uint8, rowLen)
 
 			_, err := crand.Read(buffer)
@@ -250,7 +246,7 @@ func generateMatrix(matrix Matrix) error {
 		}
 	}()
 
-	displayProgressBar := matrix.Dimension >= 100
+	displayProgressBar := matrix.Rows >= 100
 	if displayProgressBar {
 		for i := 0; i < 100; i++ {
 			fmt.Print(".")
@@ -258,8 +254,8 @@ func generateMatrix(matrix Matrix) error {
 		fmt.Printf("\r%s generating ", matrix)
 	}
 
-	for i := uint32(0); i < matrix.Dimension; i++ {
-		if displayProgressBar && 100*i%(matrix.Dimension) == 0 {
+	for i := uint32(0); i < matrix.Rows; i++ {
+		if displayProgressBar && 100*i%(matrix.Rows) == 0 {
 			fmt.Print("x")
 		}
 
@@ -504,9 +500,10 @@ func main() {
 		}
 
 		typeSize := uint32(matrix.getTypeByteSize())
-		getSlice := func(bytes []uint8, i uint32, j uint32) []uint8 {
-			begin := (i*matrix.Dimension + j) * typeSize
-			end := begin + typeSize
+		// the element (i, j) of a matrix with the given number of columns
+		getSlice := func(bytes []uint8, columns uint32, i uint32, j uint32) []uint8 {
+			begin := (uint64(i)*uint64(columns) + uint64(j)) * uint64(typeSize)
+			end := begin + uint64(typeSize)
 			return bytes[begin:end]
 		}
 
@@ -527,7 +524,7 @@ func main() {
 
 			fmt.Printf("%12s verifying ", matrix)
 
-			displayProgressBar := matrix.Dimension >= 100
+			displayProgressBar := matrix.Rows >= 100
 			if displayProgressBar {
 				for i := 0; i < 100; i++ {
 					fmt.Print(".")
@@ -535,17 +532,18 @@ func main() {
 				fmt.Printf("\r%12s verifying ", matrix)
 			}
 
-			for i := uint32(0); i < matrix.Dimension; i++ {
-				for j := uint32(0); j <= i; j++ {
-					origSlice := getSlice(origBytes, i, j)
-					transposedSlice := getSlice(transposedBytes, j, i)
+			elementCount := uint64(matrix.Rows) * uint64(matrix.Columns)
+			for i := uint32(0); i < matrix.Rows; i++ {
+				for j := uint32(0); j < matrix.Columns; j++ {
+					origSlice := getSlice(origBytes, matrix.Columns, i, j)
+					transposedSlice := getSlice(transposedBytes, matrix.Rows, j, i)
 					if !bytes.Equal(origSlice, transposedSlice) {
 						fmt.Printf("\033[2K\r%12s error: orig(%d, %d)=%s != transposed(%d, %d)=%s\n",
 							matrix, i, j, sliceToString(origSlice), j, i, sliceToString(transposedSlice))
 						continue Outer
 					}
 
-					if displayProgressBar && 100*(uint64(i)*uint64(matrix.Dimension)+uint64(j))%(bytesLen) == 0 {
+					if displayProgressBar && 100*(uint64(i)*uint64(matrix.Columns)+uint64(j))%elementCount == 0 {
 						fmt.Print("x")
 					}
 				}